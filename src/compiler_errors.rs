@@ -0,0 +1,239 @@
+//! Contains the errors the `Compiler` can return
+use std::error::Error;
+use std::fmt;
+
+use iridium::assembler::assembler_errors::AssemblerError;
+
+use parse_errors::ParseError;
+use tokens::Token;
+
+/// Errors from walking the tree. Each one carries the `Token` the `Compiler` was visiting when
+/// it went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+    /// Every register is already in use
+    RegisterExhaustion { token: Token },
+    /// An operator needed an operand that was never computed
+    OperandStackUnderflow { token: Token },
+    /// A variable was read before anything was assigned to it
+    UndefinedVariable { name: String, token: Token },
+    /// A function was called, but no function of that name is defined anywhere
+    UndefinedFunction { name: String, token: Token },
+    /// A function was called with a different number of arguments than it has parameters
+    ArityMismatch { name: String, expected: u32, found: u32, token: Token },
+    /// A float that isn't a whole number, which is all a register holds
+    UnsupportedFloat { value: f64, token: Token },
+    /// The left side of an `=` is not something that can be assigned to
+    InvalidAssignmentTarget { token: Token },
+    /// An `elif` or `else` that doesn't follow an `if`
+    DanglingBranch { token: Token },
+    /// A for loop iterates over a variable that isn't a list
+    NotAList { name: String, token: Token },
+    /// A list variable used as a value. A list can only be the iterable of a for loop.
+    ListUsedAsValue { name: String, token: Token },
+    /// A list assigned in a conditional or loop with a different number of elements than the
+    /// list it replaces, or than another list assigned to it there
+    ListLengthMismatch { name: String, expected: u32, found: u32, token: Token },
+    /// A list literal with more elements than there were registers left to hold them
+    ListTooLong { name: String, length: u32, limit: u32, token: Token },
+    /// A list or tuple literal used anywhere but the right side of an assignment
+    UnassignedList { token: Token },
+    /// A return statement outside of a function
+    ReturnOutsideFunction { token: Token },
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodegenError::RegisterExhaustion{ ref token } => {
+                write!(f, "Ran out of registers while compiling {}", token)
+            },
+            CodegenError::OperandStackUnderflow{ ref token } => {
+                write!(f, "Missing an operand for {}", token)
+            },
+            CodegenError::UndefinedVariable{ ref name, .. } => {
+                write!(f, "Variable {} is used before it is assigned", name)
+            },
+            CodegenError::UndefinedFunction{ ref name, .. } => {
+                write!(f, "Function {} is called but never defined", name)
+            },
+            CodegenError::ArityMismatch{ ref name, expected, found, .. } => {
+                let plural = |count: u32| if count == 1 { "" } else { "s" };
+                write!(f, "Function {} takes {} argument{} but is called with {}", name, expected, plural(expected), found)
+            },
+            CodegenError::UnsupportedFloat{ value, .. } => {
+                write!(f, "Can't compile {}: registers only hold integers, so a float must be a whole number", value)
+            },
+            CodegenError::InvalidAssignmentTarget{ ref token } => {
+                write!(f, "Can't assign to {}", token)
+            },
+            CodegenError::DanglingBranch{ ref token } => {
+                write!(f, "Found {} without an if before it", token)
+            },
+            CodegenError::NotAList{ ref name, .. } => {
+                write!(f, "Can't iterate over {}, which is not a list", name)
+            },
+            CodegenError::ListUsedAsValue{ ref name, .. } => {
+                write!(f, "{} is a list, which can only be looped over with for", name)
+            },
+            CodegenError::ListLengthMismatch{ ref name, expected, found, .. } => {
+                write!(f, "List {} is given {} elements here but {} elsewhere. A list assigned in a conditional or loop has to keep its length", name, found, expected)
+            },
+            CodegenError::ListTooLong{ ref name, length, limit, .. } => {
+                write!(f, "List {} has {} elements, but only {} fit in the registers that are left. Each element of a list takes a register", name, length, limit)
+            },
+            CodegenError::UnassignedList{ .. } => {
+                write!(f, "A list or tuple can only be assigned to a variable")
+            },
+            CodegenError::ReturnOutsideFunction{ .. } => {
+                write!(f, "Found return outside of a function")
+            },
+        }
+    }
+}
+
+impl CodegenError {
+    /// The `Token` the `Compiler` was visiting when it went wrong. Its span says where in the
+    /// source the error is.
+    pub fn token(&self) -> &Token {
+        match *self {
+            CodegenError::RegisterExhaustion{ ref token } |
+            CodegenError::OperandStackUnderflow{ ref token } |
+            CodegenError::UndefinedVariable{ ref token, .. } |
+            CodegenError::UndefinedFunction{ ref token, .. } |
+            CodegenError::ArityMismatch{ ref token, .. } |
+            CodegenError::UnsupportedFloat{ ref token, .. } |
+            CodegenError::InvalidAssignmentTarget{ ref token } |
+            CodegenError::DanglingBranch{ ref token } |
+            CodegenError::NotAList{ ref token, .. } |
+            CodegenError::ListUsedAsValue{ ref token, .. } |
+            CodegenError::ListLengthMismatch{ ref token, .. } |
+            CodegenError::ListTooLong{ ref token, .. } |
+            CodegenError::UnassignedList{ ref token } |
+            CodegenError::ReturnOutsideFunction{ ref token } => token,
+        }
+    }
+
+    /// Points at where in `source` the error is, the same way a `ParseError` does, if it came
+    /// from a node that has a position
+    pub fn diagnostic(&self, source: &str) -> Option<ParseError> {
+        match self.token().span() {
+            Some(span) if span.line > 0 => Some(ParseError::new(source, span.line, span.column, self.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl Error for CodegenError {
+    fn description(&self) -> &str {
+        match *self {
+            CodegenError::RegisterExhaustion{ .. } => {
+                "Ran out of registers"
+            },
+            CodegenError::OperandStackUnderflow{ .. } => {
+                "Missing an operand"
+            },
+            CodegenError::UndefinedVariable{ .. } => {
+                "Variable used before it is assigned"
+            },
+            CodegenError::UndefinedFunction{ .. } => {
+                "Function called but never defined"
+            },
+            CodegenError::ArityMismatch{ .. } => {
+                "Function called with the wrong number of arguments"
+            },
+            CodegenError::UnsupportedFloat{ .. } => {
+                "Float that isn't a whole number"
+            },
+            CodegenError::InvalidAssignmentTarget{ .. } => {
+                "Invalid assignment target"
+            },
+            CodegenError::DanglingBranch{ .. } => {
+                "Branch without an if before it"
+            },
+            CodegenError::NotAList{ .. } => {
+                "Iterating over something that is not a list"
+            },
+            CodegenError::ListUsedAsValue{ .. } => {
+                "List used as a value"
+            },
+            CodegenError::ListLengthMismatch{ .. } => {
+                "List assigned in a block with a different length"
+            },
+            CodegenError::ListTooLong{ .. } => {
+                "List too long"
+            },
+            CodegenError::UnassignedList{ .. } => {
+                "List or tuple not assigned to a variable"
+            },
+            CodegenError::ReturnOutsideFunction{ .. } => {
+                "Return outside of a function"
+            },
+        }
+    }
+}
+
+/// A line of the assembly generated by the `Compiler`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblyLine {
+    /// 1-based line number in the generated assembly
+    pub number: usize,
+    /// The text of the line
+    pub text: String,
+}
+
+impl fmt::Display for AssemblyLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} `{}`", self.number, self.text)
+    }
+}
+
+/// Errors from turning the generated assembly into bytecode. Any of these means the
+/// `Compiler` generated invalid Iridium assembly.
+#[derive(Debug, Clone)]
+pub enum CompileError {
+    /// The Iridium assembler rejected the program. `line` is set when the assembler's
+    /// errors can be traced back to a specific line.
+    Assembler { errors: Vec<AssemblerError>, line: Option<AssemblyLine> },
+    /// A line the assembler can't parse. The assembler stops at such a line without
+    /// reporting an error, so these are found before it runs.
+    InvalidInstruction { line: AssemblyLine, reason: String },
+    /// A label is used but never declared. The assembler would leave the operand out.
+    UndefinedLabel { label: String, line: AssemblyLine },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::Assembler{ ref errors, ref line } => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                match *line {
+                    Some(ref line) => write!(f, "The assembler rejected {}: {}", line, messages.join("; ")),
+                    None => write!(f, "The assembler rejected the program: {}", messages.join("; ")),
+                }
+            },
+            CompileError::InvalidInstruction{ ref line, ref reason } => {
+                write!(f, "Invalid assembly on {}: {}", line, reason)
+            },
+            CompileError::UndefinedLabel{ ref label, ref line } => {
+                write!(f, "Label {} used on {} is never declared", label, line)
+            },
+        }
+    }
+}
+
+impl Error for CompileError {
+    fn description(&self) -> &str {
+        match *self {
+            CompileError::Assembler{ .. } => {
+                "The assembler rejected the generated assembly"
+            },
+            CompileError::InvalidInstruction{ .. } => {
+                "A line of the generated assembly could not be parsed"
+            },
+            CompileError::UndefinedLabel{ .. } => {
+                "A label is used but never declared"
+            },
+        }
+    }
+}
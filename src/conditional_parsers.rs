@@ -25,10 +25,12 @@
 //! as a catch-all.
 //! 
 use nom::*;
-use nom::types::CompleteStr;
 
 use tokens::Token;
 use expression_parsers::expression;
+use lexer::{Lexemes, keyword, position, symbol};
+use parse_errors::Expected;
+use statement_parsers::body;
 
 /// Parses the start of an if block
 /// 
@@ -38,22 +40,23 @@ use expression_parsers::expression;
 /// if x > 5:
 /// ```
 /// 
-named!(pub if_block_start<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            ws!(tag!("if")) >>
-            expr: expression >>
-            ws!(tag!(":")) >>
-            body: many0!(expression) >>
-            (
-                {
-                    Token::If {
-                        expr: Box::new(expr),
-                        body: body
-                    }
+named!(pub if_block_start<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "if") >>
+        expr: expect!(Expected::Expression, expression) >>
+        expect!(Expected::Colon, call!(symbol, ":")) >>
+        body: body >>
+        end: position >>
+        (
+            {
+                Token::If {
+                    expr: Box::new(expr),
+                    body,
+                    span: start.span_to(end)
                 }
-            )
-        ) 
+            }
+        )
     )
 );
 
@@ -66,63 +69,144 @@ named!(pub if_block_start<CompleteStr, Token>,
 ///     <expressions>
 /// ```
 /// 
-named!(pub elif_block<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            ws!(tag!("elif")) >>
-            expr: expression >>
-            ws!(tag!(":")) >>
-            body: many0!(expression) >>
-            (
-                {
-                    Token::Elif {
-                        expr: Box::new(expr),
-                        body: body
-                    }
+named!(pub elif_block<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "elif") >>
+        expr: expect!(Expected::Expression, expression) >>
+        expect!(Expected::Colon, call!(symbol, ":")) >>
+        body: body >>
+        end: position >>
+        (
+            {
+                Token::Elif {
+                    expr: Box::new(expr),
+                    body,
+                    span: start.span_to(end)
+                }
+            }
+        )
+    )
+);
+
+named!(pub else_block<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "else") >>
+        expect!(Expected::Colon, call!(symbol, ":")) >>
+        body: body >>
+        end: position >>
+        (
+            {
+                Token::Else {
+                    body,
+                    span: start.span_to(end)
                 }
-            )
+            }
         )
     )
 );
 
-named!(pub else_block<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            ws!(tag!("else:")) >>
-            body: many0!(expression) >>
-            (
-                {
-                    Token::Else {
-                        body: body
-                    }
+/// Parses an if block, followed by any number of elif blocks and an optional else block.
+/// They are grouped into a single `Token::Conditional`.
+///
+/// # Example
+///
+/// ```
+/// if x > 5:
+///     <expressions>
+/// elif x > 3:
+///     <expressions>
+/// else:
+///     <expressions>
+/// ```
+///
+named!(pub conditional<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        if_branch: if_block_start >>
+        elif_branches: many0!(elif_block) >>
+        else_branch: opt!(else_block) >>
+        end: position >>
+        (
+            {
+                let mut branches = vec![if_branch];
+                branches.extend(elif_branches);
+                if let Some(else_branch) = else_branch {
+                    branches.push(else_branch);
                 }
-            )
+                Token::Conditional{ branches, span: start.span_to(end) }
+            }
         )
     )
 );
 
 mod tests {
     use super::*;
+    use lexer::lex;
+    use program_parsers::lex_source;
 
     #[test]
     fn test_parse_if_start() {
-        let result = if_block_start(CompleteStr("if x > 3:\n1+2"));
+        let lexemes = lex_source("if x > 3:\n    1+2").unwrap();
+        let result = if_block_start(Lexemes::new(&lexemes));
         assert!(result.is_ok());
-        let result = if_block_start(CompleteStr("if x > 3:\n3+2"));
+        let lexemes = lex_source("if x > 3:\n    3+2").unwrap();
+        let result = if_block_start(Lexemes::new(&lexemes));
         assert!(result.is_ok());
-        let result = if_block_start(CompleteStr("if x > 3:\n3+2\n2+1"));
+        let lexemes = lex_source("if x > 3:\n    3+2\n    2+1").unwrap();
+        let result = if_block_start(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_parse_elif_start() {
-        let result = elif_block(CompleteStr("elif x > 3:"));
+        let lexemes = lex("elif x > 3: y = 1\n").unwrap();
+        let result = elif_block(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_parse_conditional() {
+        let source = "if x > 3:\n    1+2\nelif x > 2:\n    3+2\nelif x > 1:\n    2+1\nelse:\n    2+2";
+        let lexemes = lex_source(source).unwrap();
+        let result = conditional(Lexemes::new(&lexemes));
+        let (_, tree) = result.unwrap();
+        let branches = match tree {
+            Token::Conditional{ branches, .. } => branches,
+            other => panic!("Expected a conditional, got {:?}", other),
+        };
+        let expected = vec![
+            "if x > 3:\n    1+2",
+            "elif x > 2:\n    3+2",
+            "elif x > 1:\n    2+1",
+            "else:\n    2+2",
+        ];
+        assert_eq!(branches.len(), expected.len());
+        for (branch, text) in branches.iter().zip(expected) {
+            let span = branch.span().unwrap();
+            assert_eq!(&source[span.start..span.end], text);
+        }
+        match (&branches[0], &branches[1], &branches[3]) {
+            (&Token::If{ .. }, &Token::Elif{ .. }, &Token::Else{ .. }) => {},
+            other => panic!("Expected if, elif and else, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_conditional_without_else() {
+        let lexemes = lex_source("if x > 3:\n    1+2").unwrap();
+        let result = conditional(Lexemes::new(&lexemes));
+        match result {
+            Ok((_, Token::Conditional{ branches, .. })) => assert_eq!(branches.len(), 1),
+            other => panic!("Expected a conditional, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_else_start() {
-        let result = else_block(CompleteStr("else:"));
+        let lexemes = lex("else: y = 1\n").unwrap();
+        let result = else_block(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 }
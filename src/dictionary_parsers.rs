@@ -8,74 +8,77 @@
 //! 
 
 use nom::*;
-use nom::types::CompleteStr;
 
 use tokens::Token;
 use expression_parsers::expression;
-use factor_parsers::{identifiers, identifier, factor, integer, float64};
+use factor_parsers::{identifier, factor, integer, float64};
+use lexer::{Lexemes, position, symbol, until_symbol};
 
 /// Extracts everything between `{` and `}`
-named!(dictionary_interior<CompleteStr, CompleteStr>, 
-    ws!(
-        do_parse!(
-            ws!(tag!("{")) >>
-            contents: take_until_and_consume!("}") >>
-            (
-                {
-                    contents
-                }
-            )
+named!(dictionary_interior<Lexemes, Lexemes>, 
+    do_parse!(
+        call!(symbol, "{") >>
+        contents: call!(until_symbol, "}") >>
+        call!(symbol, "}") >>
+        (
+            {
+                contents
+            }
         )
     )
 );
 
-named!(key_value_pair<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            key: expression >>
-            ws!(tag!(":")) >>
-            value: expression >>
-            (
-                {
-                    Token::DictionaryKeyValuePair{ key: Box::new(key), value: Box::new(value) }
-                }
-            )
+named!(key_value_pair<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        key: expression >>
+        call!(symbol, ":") >>
+        value: expression >>
+        end: position >>
+        (
+            {
+                Token::DictionaryKeyValuePair{ key: Box::new(key), value: Box::new(value), span: start.span_to(end) }
+            }
         )
     )
 );
 /// Top level parser for a dictionary
-named!(pub dictionary<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            dictionary_interior >>
-            (
-                {
-                    Token::Dictionary{keys: vec![], values: vec![] }
-                }
-            )
+named!(pub dictionary<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        dictionary_interior >>
+        end: position >>
+        (
+            {
+                Token::Dictionary{keys: vec![], values: vec![], span: start.span_to(end) }
+            }
         )
     )
 );
 
 mod tests {
     use super::*;
+    use lexer::lex;
 
     #[test]
     fn test_parse_empty_dictionary() {
-        let result = dictionary(CompleteStr("{}"));
+        let lexemes = lex("{}").unwrap();
+        let result = dictionary(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_parse_interior() {
-        let result = dictionary_interior(CompleteStr("{\"test\": 1}"));
+        let lexemes = lex("{\"test\": 1}").unwrap();
+        let result = dictionary_interior(Lexemes::new(&lexemes));
         println!("{:#?}", result);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_parse_key_value_pair() {
-        let result = key_value_pair(CompleteStr("10: 1"));
+        let lexemes = lex("10: 1").unwrap();
+        let result = key_value_pair(Lexemes::new(&lexemes));
         println!("{:#?}", result);
         assert!(result.is_ok());
     }
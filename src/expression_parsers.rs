@@ -1,49 +1,180 @@
 //! Contains parsers related to parsing Expressions
-use nom::types::CompleteStr;
+use nom::*;
 
 use tokens::Token;
-use term_parsers::term;
-use operator_parsers::operator;
-use function_parsers::function;
+use lexer::Lexemes;
+use parse_errors::Expected;
+use factor_parsers::factor;
+use operator_parsers::{
+    operator, prefix_operator, infix_precedence, prefix_precedence, is_right_associative, Precedence
+};
+
 /// Parses a complete expression
-/// 
+///
 /// # Expressions
-/// 
-/// An expression consists of a `Term` and an `Operator` and `Term` on the right side.
-named!(pub expression<CompleteStr, Token>,
-    do_parse!(
-        left: alt!(
-            function |
-            term
-        ) >>
-        right: many0!(
-            tuple!(
-                alt!(
-                    operator
-                ),
-                term
-            )
-        ) >>
-        (
-            {
-                Token::Expression{left: Box::new(left), right: right}
-            }
-        )
-    )
+///
+/// An expression is a `Factor`, an `Operator` in front of an expression, or two expressions
+/// with an `Operator` between them. Which operands an operator gets is decided by its
+/// `Precedence`, so
+///
+/// ```
+/// 1 + 2 * 3 < x
+/// ```
+///
+/// is parsed as `(1 + (2 * 3)) < x`. The result is a tree of `BinaryOperation`s and
+/// `UnaryOperation`s.
+named!(pub expression<Lexemes, Token>,
+    call!(expression_above, Precedence::Lowest)
 );
 
+/// Parses an expression made of operators that bind tighter than `minimum`. This is a
+/// precedence climbing parser: it parses an operand, and then keeps taking operators that bind
+/// tighter than `minimum`, each time parsing what follows at that operator's own precedence.
+/// An operator that doesn't bind tightly enough is left for a caller further up.
+pub fn expression_above(input: Lexemes, minimum: Precedence) -> IResult<Lexemes, Token> {
+    let (mut rest, mut left) = operand(input)?;
+    loop {
+        let (after_operator, operator) = match operator(rest) {
+            Ok(result) => result,
+            Err(Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        let precedence = match infix_precedence(&operator) {
+            Some(precedence) if precedence > minimum => precedence,
+            _ => break,
+        };
+        // A right associative operator lets the same operator into its right operand
+        let right_minimum = if is_right_associative(&operator) { precedence.below() } else { precedence };
+        let (after_right, right) = expect!(after_operator, Expected::Expression, call!(expression_above, right_minimum))?;
+        left = Token::BinaryOperation{
+            operator: Box::new(operator),
+            left: Box::new(left),
+            right: Box::new(right),
+            span: input.span_to(after_right),
+        };
+        rest = after_right;
+    }
+    Ok((rest, left))
+}
+
+/// Parses the left operand of an operator: a `Factor`, or a prefix operator and its operand
+fn operand(input: Lexemes) -> IResult<Lexemes, Token> {
+    let (after_operator, operator) = match prefix_operator(input) {
+        Ok(result) => result,
+        Err(Err::Error(_)) => return factor(input),
+        Err(e) => return Err(e),
+    };
+    let precedence = prefix_precedence(&operator).unwrap_or(Precedence::Unary);
+    let (rest, operand) = expect!(after_operator, Expected::Expression, call!(expression_above, precedence))?;
+    let span = input.span_to(rest);
+    Ok((rest, Token::UnaryOperation{ operator: Box::new(operator), operand: Box::new(operand), span }))
+}
+
 mod tests {
     use super::*;
+    use lexer::lex;
+
+    /// Parses `source` and writes the tree with every `BinaryOperation` in parentheses
+    fn grouping(source: &str) -> String {
+        let lexemes = lex(source).unwrap();
+        let (rest, tree) = expression(Lexemes::new(&lexemes)).unwrap();
+        assert!(rest.is_empty());
+        group(&tree)
+    }
+
+    fn group(node: &Token) -> String {
+        match *node {
+            Token::BinaryOperation{ ref operator, ref left, ref right, .. } => {
+                format!("({} {} {})", group(left), operator, group(right))
+            },
+            Token::UnaryOperation{ ref operator, ref operand, .. } => {
+                format!("({} {})", operator, group(operand))
+            },
+            Token::Factor{ ref value, .. } => group(value),
+            ref other => other.to_string(),
+        }
+    }
 
     #[test]
     fn test_parse_expression() {
-        let result = expression(CompleteStr("3>4"));
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("3>4").unwrap();
+        let result = expression(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
     }
 
     #[test]
     fn test_parse_nested_expression() {
-        let result = expression(CompleteStr("(3*4)+1"));
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("(3*4)+1").unwrap();
+        let result = expression(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_parse_nested_products() {
+        assert_eq!(grouping("3*4"), "(3 * 4)");
+        assert_eq!(grouping("(3*4)*2"), "((3 * 4) * 2)");
+        assert_eq!(grouping("((3*4)*2)"), "((3 * 4) * 2)");
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(grouping("1 + 2 * 3"), "(1 + (2 * 3))");
+        assert_eq!(grouping("2 * 3 + 1"), "((2 * 3) + 1)");
+        assert_eq!(grouping("1 + 2 < 3 * 4"), "((1 + 2) < (3 * 4))");
+        assert_eq!(grouping("a or b and c"), "(a or (b and c))");
+        assert_eq!(grouping("a and b or c"), "((a and b) or c)");
+        assert_eq!(grouping("a < b and b < c"), "((a < b) and (b < c))");
+    }
+
+    #[test]
+    fn test_prefix_operators() {
+        assert_eq!(grouping("-x * 2"), "((- x) * 2)");
+        assert_eq!(grouping("-(-3)"), "(- (- 3))");
+        assert_eq!(grouping("- - 3"), "(- (- 3))");
+        assert_eq!(grouping("+x"), "(+ x)");
+        assert_eq!(grouping("2 - -x"), "(2 - (- x))");
+        assert_eq!(grouping("-1"), "(- 1)");
+        assert_eq!(grouping("-1.02"), "(- 1.02)");
+        assert_eq!(grouping("-0b1010 * 2"), "((- 10) * 2)");
+        assert_eq!(grouping("3-1"), "(3 - 1)");
+        assert_eq!(grouping("not not x"), "(not (not x))");
+        assert_eq!(grouping("not x == y"), "(not (x == y))");
+        assert_eq!(grouping("not x and y"), "((not x) and y)");
+        assert_eq!(grouping("a or not b"), "(a or (not b))");
+    }
+
+    #[test]
+    fn test_prefix_operator_without_operand() {
+        let lexemes = lex("x + -").unwrap();
+        match expression(Lexemes::new(&lexemes)) {
+            Err(Err::Failure(_)) => {},
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_associativity() {
+        assert_eq!(grouping("1 - 2 - 3"), "((1 - 2) - 3)");
+        assert_eq!(grouping("8 / 4 / 2"), "((8 / 4) / 2)");
+        assert_eq!(grouping("2 ** 3 ** 2"), "(2 ** (3 ** 2))");
+        assert_eq!(grouping("7 // 2 % 3"), "((7 // 2) % 3)");
+    }
+
+    #[test]
+    fn test_exponent_precedence() {
+        assert_eq!(grouping("2 ** 3 + 1"), "((2 ** 3) + 1)");
+        assert_eq!(grouping("2 * 3 ** 2"), "(2 * (3 ** 2))");
+        assert_eq!(grouping("-2 ** 2"), "(- (2 ** 2))");
+        assert_eq!(grouping("2 ** -1"), "(2 ** (- 1))");
+        assert_eq!(grouping("a != b and c"), "((a != b) and c)");
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        assert_eq!(grouping("a | b ^ c & d"), "(a | (b ^ (c & d)))");
+        assert_eq!(grouping("a & 1 << 2 + 1"), "(a & (1 << (2 + 1)))");
+        assert_eq!(grouping("flags & 4 == 4"), "((flags & 4) == 4)");
+        assert_eq!(grouping("~a & b"), "((~ a) & b)");
+        assert_eq!(grouping("1 << 2 << 3"), "((1 << 2) << 3)");
     }
 }
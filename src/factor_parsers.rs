@@ -1,74 +1,119 @@
 //! Contains parsers for `Factors`
 use nom::*;
-use nom::types::CompleteStr;
 
 use tokens::Token;
 use expression_parsers::expression;
 use function_parsers::{
     function_call, return_statement
 };
+use lexer::{Lexeme, Lexemes, LexemeKind, Number, keyword, lexeme, number_value, position, string_value, symbol};
+use parse_errors::Expected;
+use list_parsers::list;
 
-/// Parser for a 64-bit float. A float can be negative, and must contain a `.`.
+/// Parser for a 64-bit float. A float must contain a `.` or an exponent. A `-` in front of it
+/// is a prefix operator, which `expression` parses.
 /// 
 /// # Example
 /// 
 /// ```
 /// x = 4.5
-/// y = -4.5
+/// y = .5
+/// z = 1_000.0e-3
 /// ```
-named!(pub float64<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            sign: opt!(tag!("-")) >>
-            left_nums: digit >>
-            tag!(".") >>
-            right_nums: digit >>
-            (
-                {
-                    let mut tmp = String::from("");
-                    if sign.is_some() {
-                        tmp.push_str("-");
-                    }
-                    tmp.push_str(&left_nums.to_string());
-                    tmp.push_str(".");
-                    tmp.push_str(&right_nums.to_string());
-                    let converted = tmp.parse::<f64>().unwrap();
-                    Token::Factor{ value: Box::new(Token::Float{value: converted}) }
-                }
-            )
+named!(pub float64<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        value: map_opt!(call!(lexeme, LexemeKind::Float), |number: &Lexeme| {
+            match number_value(&number.text) {
+                Ok(Number::Float(value)) => Some(value),
+                _ => None,
+            }
+        }) >>
+        end: position >>
+        (
+            {
+                let span = start.span_to(end);
+                Token::Factor{ value: Box::new(Token::Float{value, span}), span }
+            }
         )
     )
 );
 
-/// Parser for a signed 64-bit integer.
+/// Parser for an integer. It can be written in decimal, hex, octal or binary, with `_`s
+/// between the digits. The lexer has already checked that it fits in 32 bits. Like a float, a
+/// negative integer is a prefix `-` applied to it.
 /// 
 /// # Example
 /// 
 /// ```
 /// x = 4
-/// y = -4
+/// y = 0b100
+/// z = 0xFF_FF
 /// ```
-named!(pub integer<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            sign: opt!(tag!("-")) >>
-            reg_num: digit >>
-            (
-                {
-                    let mut tmp = String::from("");
-                    if sign.is_some() {
-                        tmp.push_str("-");
-                    }
-                    tmp.push_str(&reg_num.to_string());
-                    let converted = tmp.parse::<i64>().unwrap();
-                    Token::Integer{ value: converted }
-                }
-            )
+named!(pub integer<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        value: map_opt!(call!(lexeme, LexemeKind::Integer), |number: &Lexeme| {
+            match number_value(&number.text) {
+                Ok(Number::Integer(value)) => Some(value),
+                _ => None,
+            }
+        }) >>
+        end: position >>
+        (
+            Token::Integer{ value, span: start.span_to(end) }
+        )
+    )
+);
+
+/// Parser for a string literal, in single or double quotes. Its escape sequences are replaced
+/// by the characters they stand for.
+///
+/// # Example
+///
+/// ```
+/// greeting = "Hello,\tworld!\n"
+/// ```
+named!(pub string<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        value: map_res!(call!(lexeme, LexemeKind::Str), |literal: &Lexeme| string_value(&literal.text)) >>
+        end: position >>
+        (
+            Token::String{ value, span: start.span_to(end) }
+        )
+    )
+);
+
+/// Parser for `True` and `False`. In a register, `True` is 1 and `False` is 0.
+named!(pub boolean<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        word: alt!(
+            call!(keyword, "True") |
+            call!(keyword, "False")
+        ) >>
+        end: position >>
+        (
+            Token::Boolean{ value: word.text == "True", span: start.span_to(end) }
+        )
+    )
+);
+
+/// Parser for `None`, the value of nothing. In a register it is 0, the same as `False`, so it
+/// is false in a condition.
+named!(pub none<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "None") >>
+        end: position >>
+        (
+            Token::None{ span: start.span_to(end) }
         )
     )
 );
 
-/// Parse for a variable identifier
+/// Parser for a variable, which is a single name
 /// 
 /// # Example
 /// 
@@ -76,65 +121,66 @@ named!(pub integer<CompleteStr, Token>,
 /// x
 /// ````
 /// 
-/// An Identifier can consist only of letters and are case-sensitive.
-named!(pub identifiers<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            values: many1!(identifier) >>
-            (
-                {
-                    let mut converted_vec = vec![];
-                    for value in values {
-                        converted_vec.push(value.to_string());
-                    }
-                    let identifier = Token::Identifier{ values: converted_vec };
-                    Token::Factor{ value: Box::new(identifier) }
-                }
-            )
+/// Two names in a row, like `x y`, are not a variable. Names that belong together are
+/// separated by commas in `Arguments`, `Parameters` or a `Tuple`.
+named!(pub variable<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        name: identifier >>
+        end: position >>
+        (
+            Token::Identifier{ name, span: start.span_to(end) }
         )
     )
 );
 
-named!(pub identifier<CompleteStr, String>,
-    ws!(
-        do_parse!(
-            not!(reserved) >>
-            value: ws!(alphanumeric) >>
-            opt!(ws!(tag!(","))) >>
-            (
-                {
-                    value.to_string()
+/// Parses an expression in parentheses, or a tuple. Every element of a tuple but the last is
+/// followed by a `,`, and so is the last one if it is the only one: `()`, `(x,)` and `(x, y)`
+/// are tuples, while `(x)` is just `x` and `(,)` is an error.
+named!(pub parenthesized<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(symbol, "(") >>
+        elements: separated_list!(call!(symbol, ","), expression) >>
+        trailing_comma: cond!(!elements.is_empty(), call!(symbol, ",")) >>
+        expect!(Expected::ClosingParen, call!(symbol, ")")) >>
+        end: position >>
+        (
+            {
+                let mut elements = elements;
+                if elements.len() == 1 && trailing_comma.is_none() {
+                    elements.remove(0)
+                } else {
+                    Token::Tuple{ elements, span: start.span_to(end) }
                 }
-            )
+            }
         )
     )
 );
 
-named!(pub end_of_file<CompleteStr, Token>, 
-    ws!(
-        do_parse!(
-            eof!() >>
-            (
-                Token::Eof
-            )
+/// Parser for a name, such as the name of a variable, function or argument
+named!(pub identifier<Lexemes, String>,
+    do_parse!(
+        value: call!(lexeme, LexemeKind::Identifier) >>
+        (
+            {
+                value.text.to_string()
+            }
         )
     )
 );
-named!(pub reserved<CompleteStr, CompleteStr>,
-    ws!(
-        peek!(
-            alt!(
-                complete!(tag!("def")) |
-                complete!(tag!("if")) |
-                complete!(tag!("elif")) |
-                complete!(tag!("else")) |
-                complete!(tag!("return"))
-            )
+
+named!(pub end_of_file<Lexemes, Token>,
+    do_parse!(
+        eof!() >>
+        (
+            Token::Eof
         )
     )
 );
-/// Parser for a `Factor`. A Factor consists of an integer, float, identifier,
-/// or a parenthized expression
+
+/// Parser for a `Factor`. A Factor consists of an integer, float, string, `True`, `False`,
+/// `None`, function call, list, identifier, or a parenthized expression
 /// 
 /// # Example
 /// 
@@ -142,59 +188,142 @@ named!(pub reserved<CompleteStr, CompleteStr>,
 /// (1+2)
 /// ```
 /// 
-named!(pub factor<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            f: alt!(
-                integer |
-                float64 |
-                function_call |
-                identifiers |
-                ws!(delimited!( tag_s!("("), expression, tag_s!(")") ))
-            ) >>
-
-            (
-                {
-                    Token::Factor{value: Box::new(f)}
-                }
-            )
+named!(pub factor<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        f: alt!(
+            integer |
+            float64 |
+            string |
+            boolean |
+            none |
+            function_call |
+            list |
+            variable |
+            parenthesized
+        ) >>
+        end: position >>
+        (
+            {
+                Token::Factor{value: Box::new(f), span: start.span_to(end)}
+            }
         )
     )
 );
 
 mod tests {
     use super::*;
+    use lexer::{lex, Span};
+
     #[test]
     fn test_factor() {
-        let test_program = CompleteStr("(1+2)");
-        let result = factor(test_program);
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("(1+2)").unwrap();
+        let result = factor(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
         let (_, tree) = result.unwrap();
+        assert_eq!(tree.span(), Some(Span{ start: 0, end: 5, line: 1, column: 1 }));
     }
 
     #[test]
     fn test_parse_floats() {
-        let test_floats = vec!["100.4", "1.02", "-1.02"];
+        let test_floats = vec!["100.4", "1.02", "1e-2"];
         for o in test_floats {
             let parsed_o = o.parse::<f64>().unwrap();
-            let result = float64(CompleteStr(o));
-            assert_eq!(result.is_ok(), true);
+            let lexemes = lex(o).unwrap();
+            let result = float64(Lexemes::new(&lexemes));
+            assert!(result.is_ok());
         }
     }
 
     #[test]
     fn test_parse_integer() {
-        let test_integers = vec!["0", "-1", "1"];
+        let test_integers = vec!["0", "1", "2147483647"];
         for o in test_integers {
             let parsed_o = o.parse::<i64>().unwrap();
-            let result = integer(CompleteStr(o));
-            assert_eq!(result.is_ok(), true);
+            let lexemes = lex(o).unwrap();
+            let result = integer(Lexemes::new(&lexemes));
+            assert!(result.is_ok());
         }
     }
 
+    #[test]
+    fn test_parse_variable() {
+        let lexemes = lex("tile_count").unwrap();
+        let (_, token) = variable(Lexemes::new(&lexemes)).unwrap();
+        assert_eq!(token, Token::Identifier{ name: "tile_count".to_string(), span: Span{ start: 0, end: 10, line: 1, column: 1 } });
+        let lexemes = lex("a b").unwrap();
+        let (rest, _) = factor(Lexemes::new(&lexemes)).unwrap();
+        assert_eq!(rest.first().map(|lexeme| lexeme.text.as_str()), Some("b"));
+    }
+
+    #[test]
+    fn test_parse_tuples() {
+        for &(source, expected) in &[("()", "()"), ("(a,)", "(a,)"), ("(a, b)", "(a, b)"), ("(a, 1 + 2,)", "(a, 1 + 2)"), ("((a, b), c)", "((a, b), c)")] {
+            let lexemes = lex(source).unwrap();
+            match parenthesized(Lexemes::new(&lexemes)) {
+                Ok((rest, tuple @ Token::Tuple{ .. })) => {
+                    assert!(rest.is_empty(), "{}", source);
+                    assert_eq!(tuple.to_string(), expected);
+                },
+                other => panic!("Expected a tuple for {}, got {:?}", source, other),
+            }
+        }
+        let lexemes = lex("(a)").unwrap();
+        match parenthesized(Lexemes::new(&lexemes)) {
+            Ok((_, Token::Factor{ value, .. })) => assert_eq!(value.to_string(), "a"),
+            other => panic!("Expected a variable, got {:?}", other),
+        }
+        for source in &["(,)", "(a,,)"] {
+            let lexemes = lex(source).unwrap();
+            match parenthesized(Lexemes::new(&lexemes)) {
+                Err(Err::Failure(_)) => {},
+                other => panic!("Expected {} to fail, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_string() {
+        let lexemes = lex(r#"'it\'s "quoted"\n'"#).unwrap();
+        let (_, token) = string(Lexemes::new(&lexemes)).unwrap();
+        assert_eq!(token, Token::String{ value: "it's \"quoted\"\n".to_string(), span: Span{ start: 0, end: 18, line: 1, column: 1 } });
+    }
+
+    #[test]
+    fn test_parse_numeric_literals() {
+        for &(source, expected) in &[("0xFF", 255), ("0b1010", 10), ("0o777", 511), ("1_000_000", 1_000_000), ("0xFFFF_FFFF", 4_294_967_295)] {
+            let lexemes = lex(source).unwrap();
+            match integer(Lexemes::new(&lexemes)) {
+                Ok((_, Token::Integer{ value, .. })) => assert_eq!(value, expected, "{}", source),
+                other => panic!("Unable to parse {}: {:?}", source, other),
+            }
+        }
+        for &(source, expected) in &[(".5", 0.5), ("1e9", 1e9), ("2.5E-1", 0.25), ("1_0.0_1", 10.01), ("5.", 5.0)] {
+            let lexemes = lex(source).unwrap();
+            match float64(Lexemes::new(&lexemes)) {
+                Ok((_, Token::Factor{ value, .. })) => assert_eq!(*value, Token::Float{ value: expected, span: value.span().unwrap() }, "{}", source),
+                other => panic!("Unable to parse {}: {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_constants() {
+        let lexemes = lex("True False None").unwrap();
+        let (rest, token) = boolean(Lexemes::new(&lexemes)).unwrap();
+        assert_eq!(token, Token::Boolean{ value: true, span: Span{ start: 0, end: 4, line: 1, column: 1 } });
+        let (rest, token) = boolean(rest).unwrap();
+        assert_eq!(token, Token::Boolean{ value: false, span: Span{ start: 5, end: 10, line: 1, column: 6 } });
+        assert!(boolean(rest).is_err());
+        let (rest, token) = none(rest).unwrap();
+        assert_eq!(token, Token::None{ span: Span{ start: 11, end: 15, line: 1, column: 12 } });
+        assert!(rest.is_empty());
+    }
+
     #[test]
     fn test_parse_identifier() {
-        let result = identifier(CompleteStr("x"));
+        let lexemes = lex("x").unwrap();
+        let result = identifier(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 }
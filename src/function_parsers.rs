@@ -1,197 +1,253 @@
 //! This contains functions related to parsing functions. In Palladium, a function
 //! looks like this:
 //! def somefunction(arg1, arg2):
+//!     """What the function does"""
 //!     expressions
-//! \n
+//!     return arg1
 
 use nom::*;
-use nom::types::CompleteStr;
 
 use tokens::Token;
 use expression_parsers::expression;
-use factor_parsers::{identifiers, identifier, factor};
+use statement_parsers::body;
+use factor_parsers::identifier;
+use lexer::{Lexemes, keyword, position, symbol};
+use parse_errors::Expected;
 
 /// Function to extract a function name. A function name is comprised of:
 /// `def` `a-zA-Z0-9`
-named!(pub function_name<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            ws!(tag!("def")) >>
-            func_name: take_until!("(") >>
-            ( 
-                {
-                    Token::FunctionName{ name: func_name.to_string() }
-                }
-            )
+named!(pub function_name<Lexemes, Token>,
+    do_parse!(
+        call!(keyword, "def") >>
+        start: position >>
+        name: expect!(Expected::Identifier, identifier) >>
+        end: position >>
+        (
+            {
+                Token::FunctionName{ name, span: start.span_to(end) }
+            }
         )
     )
 );
 
-/// Function to look for an individual arg. 
-/// In `def func(x, y, z):` it is meant to look for x, y and z
-named!(function_arg<CompleteStr, String>,
-    ws!(
-        do_parse!(
-            // Any alphanumeric counts as a valid character in a function
-            arg: ws!(identifier) >>
-            (
-                {
-                    arg.to_string()
-                }
-            )
+/// Extracts the names of the arguments from a function definition. In `def func(x, y, z):`
+/// they are x, y and z. The last one may be followed by a `,`, but `(,)` is an error.
+named!(pub parameters<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(symbol, "(") >>
+        names: separated_list!(call!(symbol, ","), identifier) >>
+        cond!(!names.is_empty(), call!(symbol, ",")) >>
+        expect!(after_elements(&names), call!(symbol, ")")) >>
+        end: position >>
+        (
+            {
+                Token::Parameters{ names, span: start.span_to(end) }
+            }
         )
     )
 );
 
-/// Extracts all of the arguments from a function definition
-/// This calls `function_arg` repeatedly to get all the args and put them in a list
-named!(pub function_args<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            tag!("(") >>
-            args: many0!(function_arg) >>
-            tag!(")") >>
-            (
-                {
-                    Token::FunctionArgs{ args: args }
-                }
-            )
+/// Extracts the values passed to a function from a call. Each one can be any expression, so
+/// in `func(x, y + 1, g(z))` they are `x`, `y + 1` and `g(z)`. The last one may be followed
+/// by a `,`, but `(,)` is an error.
+named!(pub arguments<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(symbol, "(") >>
+        values: separated_list!(call!(symbol, ","), expression) >>
+        cond!(!values.is_empty(), call!(symbol, ",")) >>
+        expect!(after_elements(&values), call!(symbol, ")")) >>
+        end: position >>
+        (
+            {
+                Token::Arguments{ values, span: start.span_to(end) }
+            }
         )
     )
 );
 
-/// Extracts all the expressions that make up a function body
-named!(pub function_body<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            expressions: many0!(expression) >>
-            (
-                {
-                    Token::FunctionBody{ expressions: expressions }
-                }
-            )
+/// What has to come after the parameters or arguments parsed so far: a `,` or the `)`, or
+/// just the `)` if there are none
+fn after_elements<T>(elements: &[T]) -> Expected {
+    if elements.is_empty() { Expected::ClosingParen } else { Expected::CommaOrClosingParen }
+}
+
+/// Extracts all the statements that make up a function body
+named!(pub function_body<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        expressions: body >>
+        end: position >>
+        (
+            {
+                Token::FunctionBody{ expressions, span: start.span_to(end) }
+            }
         )
     )
 );
 
 /// Top level function that uses all of the previously defined functions
 /// to parse out a complete function
-named!(pub function<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            fname: function_name >>
-            args: function_args >>
-            ws!(tag!(":")) >>
-            body: function_body >>
-            rs: return_statement >>
-            (
-                {
-                    Token::Function{ name: Box::new(fname), args: Box::new(args), body: Box::new(body), return_statement: Box::new(rs) }
-                }
-            )
+named!(pub function<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        fname: function_name >>
+        parameters: expect!(Expected::OpeningParen, parameters) >>
+        expect!(Expected::Colon, call!(symbol, ":")) >>
+        body: function_body >>
+        end: position >>
+        (
+            {
+                let span = start.span_to(end);
+                let (docstring, body) = docstring(body);
+                Token::Function{ name: Box::new(fname), parameters: Box::new(parameters), body: Box::new(body), docstring, span }
+            }
         )
     )
 );
 
-named!(pub function_call<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            name: identifier >>
-            parameters: function_args >>
-            (
-                {
-                    Token::FunctionCall{
-                        name: name,
-                        parameters: Box::new(parameters)
-                    }
-                }
-            )
-        )
-    )
-);
-
-named!(pub return_arg<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            f: factor >>
-            opt!(ws!(tag!(","))) >>
-            (
-                {
-                    Token::Factor{ value: Box::new(f) }
-                }
-            )
-        )
-    )
-);
+/// Takes the docstring out of a function body. Like in Python, a docstring is a string on its
+/// own as the first statement of the body. It isn't compiled, so it costs nothing at runtime.
+fn docstring(body: Token) -> (Option<String>, Token) {
+    match body {
+        Token::FunctionBody{ mut expressions, span } => {
+            let docstring = match expressions.first() {
+                Some(Token::Factor{ value, .. }) => match **value {
+                    Token::String{ ref value, .. } => Some(value.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if docstring.is_some() {
+                expressions.remove(0);
+            }
+            (docstring, Token::FunctionBody{ expressions, span })
+        },
+        other => (None, other),
+    }
+}
 
-named!(pub return_args<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            args: many0!(return_arg) >>
-            ws!(tag!(";")) >>
-            (
-                {
-                    Token::ReturnArgs{ args: args }
+named!(pub function_call<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        name: identifier >>
+        arguments: arguments >>
+        end: position >>
+        (
+            {
+                Token::FunctionCall{
+                    name,
+                    arguments: Box::new(arguments),
+                    span: start.span_to(end)
                 }
-            )
+            }
         )
     )
 );
 
-named!(pub return_statement<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            tag!("return") >>
-            arg: return_arg >>
-            ws!(tag!(";")) >>
-            
-            (
-                {
-                    Token::ReturnStatement{ parameters: Box::new(arg) }
-                }
-            )
+/// Parses a return statement. It can be anywhere in a function body, and the `;` at the
+/// end is optional.
+///
+/// # Example
+///
+/// ```
+/// return x + 1
+/// ```
+named!(pub return_statement<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "return") >>
+        arg: expect!(Expected::Expression, expression) >>
+        opt!(call!(symbol, ";")) >>
+        end: position >>
+        (
+            {
+                Token::ReturnStatement{ parameters: Box::new(arg), span: start.span_to(end) }
+            }
         )
     )
 );
 
 mod tests {
     use super::*;
+    use lexer::lex;
+    use program_parsers::lex_source;
 
     #[test]
     fn test_parse_function_header() {
-        let result = function_name(CompleteStr("def testfunc("));
+        let lexemes = lex("def testfunc(").unwrap();
+        let result = function_name(Lexemes::new(&lexemes));
         assert!(result.is_ok());
         
     }
 
     #[test]
-    fn test_parse_function_arg() {
-        let result = function_arg(CompleteStr("arg1)"));
-        assert!(result.is_ok());
-        let result = function_arg(CompleteStr("arg1,"));
-        assert!(result.is_ok());
+    fn test_parse_parameters() {
+        for &(source, expected) in &[("()", "()"), ("(arg1)", "(arg1)"), ("(arg1,)", "(arg1)"), ("(arg1, arg2)", "(arg1, arg2)")] {
+            let lexemes = lex(source).unwrap();
+            match parameters(Lexemes::new(&lexemes)) {
+                Ok((_, names @ Token::Parameters{ .. })) => assert_eq!(names.to_string(), expected),
+                other => panic!("Unable to parse {}: {:?}", source, other),
+            }
+        }
     }
 
     #[test]
-    fn test_parse_function_args() {
-        let result = function_args(CompleteStr("(arg1, arg2)"));
+    fn test_names_must_be_separated() {
+        for source in &["(a b)", "(a, b c)", "(a,, b)", "(,)"] {
+            let lexemes = lex(source).unwrap();
+            match parameters(Lexemes::new(&lexemes)) {
+                Err(Err::Failure(_)) => {},
+                other => panic!("Expected {} to fail, got {:?}", source, other),
+            }
+            match arguments(Lexemes::new(&lexemes)) {
+                Err(Err::Failure(_)) => {},
+                other => panic!("Expected {} to fail, got {:?}", source, other),
+            }
+        }
     }
 
     #[test]
     fn test_parse_function_def() {
-        let test_function = CompleteStr("def test(arg1, arg2):\n\t1+2\n\treturn 0;\n");
-        let result = function(test_function);
+        let lexemes = lex_source("def test(arg1, arg2):\n\t1+2\n\treturn 0;\n").unwrap();
+        let result = function(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_parse_docstring() {
+        let lexemes = lex_source("def f(x):\n    \"\"\"Doubles x.\n\n    Only for integers.\"\"\"\n    return x * 2\n").unwrap();
+        match function(Lexemes::new(&lexemes)) {
+            Ok((_, Token::Function{ docstring, body, .. })) => {
+                assert_eq!(docstring, Some("Doubles x.\n\n    Only for integers.".to_string()));
+                match *body {
+                    Token::FunctionBody{ ref expressions, .. } => assert_eq!(expressions.len(), 1),
+                    ref other => panic!("Expected a function body, got {:?}", other),
+                }
+            },
+            other => panic!("Unable to parse a function with a docstring: {:?}", other),
+        }
+        let lexemes = lex_source("def f(x): 'Returns nothing.'\ndef g(x):\n    x = 'not a docstring'\n").unwrap();
+        let (rest, f) = function(Lexemes::new(&lexemes)).unwrap();
+        let (_, g) = function(rest).unwrap();
+        match (f, g) {
+            (Token::Function{ docstring: f_docstring, .. }, Token::Function{ docstring: g_docstring, .. }) => {
+                assert_eq!(f_docstring, Some("Returns nothing.".to_string()));
+                assert_eq!(g_docstring, None);
+            },
+            other => panic!("Expected two functions, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_function_call() {
-        let test_function = CompleteStr(
+        let lexemes = lex_source(
 r#"
 test(arg1, arg2)
 "#
-);
-        let result = function_call(test_function);
+).unwrap();
+        let result = function_call(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 }
@@ -0,0 +1,250 @@
+//! Contains the pass that turns the indentation of a source file into explicit block markers.
+//!
+//! The parsers only see `Lexeme`s, so they can't tell where a line starts or how far it is
+//! indented. Before parsing, the lines that open or close a block get `Indent` or `Dedent`
+//! lexemes in front of them:
+//!
+//! ```
+//! if x > 5:
+//!     y = 1
+//! z = 2
+//! ```
+//!
+//! becomes `if x > 5 : ⏎ → y = 1 ⏎ ← z = 2 ⏎`, with `⏎`, `→` and `←` standing for `Newline`,
+//! `Indent` and `Dedent`. Lines inside brackets continue the line the brackets were opened on.
+use std::error::Error;
+use std::fmt;
+
+use lexer::{Lexeme, LexemeKind, Span};
+
+/// A line whose indentation doesn't fit the blocks around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndentationError {
+    /// 1-based line number in the source
+    pub line: usize,
+    /// 1-based column of the first character on the line
+    pub column: usize,
+    pub kind: IndentationErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndentationErrorKind {
+    /// A line is indented further, but the line before it doesn't open a block
+    UnexpectedIndent,
+    /// A line ending in `:` isn't followed by an indented line. Holds the line of the `:`.
+    ExpectedIndentedBlock { opened_on: usize },
+    /// A line is indented less than its block, but not as far as any block around it. This is
+    /// also what mixing tabs and spaces looks like.
+    InconsistentIndentation,
+}
+
+impl fmt::Display for IndentationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl fmt::Display for IndentationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IndentationErrorKind::UnexpectedIndent => write!(f, "unexpected indent"),
+            IndentationErrorKind::ExpectedIndentedBlock{ opened_on } => {
+                write!(f, "expected an indented block after line {}", opened_on)
+            },
+            IndentationErrorKind::InconsistentIndentation => {
+                write!(f, "indentation doesn't match any enclosing block")
+            },
+        }
+    }
+}
+
+impl Error for IndentationError {
+    fn description(&self) -> &str {
+        match self.kind {
+            IndentationErrorKind::UnexpectedIndent => "Unexpected indent",
+            IndentationErrorKind::ExpectedIndentedBlock{ .. } => "Expected an indented block",
+            IndentationErrorKind::InconsistentIndentation => "Indentation doesn't match any enclosing block",
+        }
+    }
+}
+
+/// Adds `Indent` and `Dedent` lexemes to the `lexemes` of `source`. Only the `Newline`s that
+/// end a logical line are kept, and the last line gets one if it didn't have it. Every block
+/// is closed at the end of the source.
+pub fn mark_blocks(source: &str, lexemes: Vec<Lexeme>) -> Result<Vec<Lexeme>, IndentationError> {
+    let mut marked: Vec<Lexeme> = vec![];
+    // The indentation of every open block, outermost first
+    let mut levels: Vec<&str> = vec![""];
+    let mut bracket_depth = 0;
+    // The line that ended in `:`, if the next line has to open a block
+    let mut block_opened_on = None;
+    let mut at_line_start = true;
+
+    for lexeme in lexemes {
+        if lexeme.kind == LexemeKind::Newline {
+            // Blank lines and line breaks inside brackets don't end a logical line
+            if bracket_depth > 0 || at_line_start {
+                continue;
+            }
+            if ends_with_colon(&marked) {
+                block_opened_on = Some(lexeme.span.line);
+            }
+            marked.push(lexeme);
+            at_line_start = true;
+            continue;
+        }
+
+        if at_line_start {
+            let Span{ line, column, .. } = lexeme.span;
+            let line_start = source[..lexeme.span.start].rfind('\n').map_or(0, |index| index + 1);
+            let indentation = &source[line_start..lexeme.span.start];
+            let current = levels[levels.len() - 1];
+            if indentation.len() > current.len() && indentation.starts_with(current) {
+                if block_opened_on.is_none() {
+                    return Err(IndentationError{ line, column, kind: IndentationErrorKind::UnexpectedIndent });
+                }
+                levels.push(indentation);
+                marked.push(Lexeme::marker(LexemeKind::Indent, lexeme.span));
+            } else if let Some(opened_on) = block_opened_on {
+                let kind = IndentationErrorKind::ExpectedIndentedBlock{ opened_on };
+                return Err(IndentationError{ line, column, kind });
+            } else if indentation != current {
+                while levels[levels.len() - 1].len() > indentation.len() {
+                    levels.pop();
+                    marked.push(Lexeme::marker(LexemeKind::Dedent, lexeme.span));
+                }
+                if levels[levels.len() - 1] != indentation {
+                    return Err(IndentationError{ line, column, kind: IndentationErrorKind::InconsistentIndentation });
+                }
+            }
+            block_opened_on = None;
+            at_line_start = false;
+        }
+
+        if lexeme.kind == LexemeKind::Symbol {
+            match lexeme.text.as_str() {
+                "(" | "[" | "{" => bracket_depth += 1,
+                ")" | "]" | "}" if bracket_depth > 0 => bracket_depth -= 1,
+                _ => {},
+            }
+        }
+        marked.push(lexeme);
+    }
+
+    // Put the markers at the end of the last line, not on the empty line after it
+    let end = end_of(source.trim_end());
+    if !at_line_start {
+        if ends_with_colon(&marked) {
+            block_opened_on = Some(end.line);
+        }
+        marked.push(Lexeme::marker(LexemeKind::Newline, end));
+    }
+    if let Some(opened_on) = block_opened_on {
+        let kind = IndentationErrorKind::ExpectedIndentedBlock{ opened_on };
+        return Err(IndentationError{ line: source.lines().count() + 1, column: 1, kind });
+    }
+    for _ in 1..levels.len() {
+        marked.push(Lexeme::marker(LexemeKind::Dedent, end));
+    }
+    Ok(marked)
+}
+
+/// Returns the position just after the last character of `source`
+pub fn end_of(source: &str) -> Span {
+    let line_start = source.rfind('\n').map_or(0, |index| index + 1);
+    let line = source.matches('\n').count() + 1;
+    let column = source[line_start..].chars().count() + 1;
+    Span{ start: source.len(), end: source.len(), line, column }
+}
+
+fn ends_with_colon(lexemes: &[Lexeme]) -> bool {
+    match lexemes.last() {
+        Some(last) => last.kind == LexemeKind::Symbol && last.text == ":",
+        None => false,
+    }
+}
+
+mod tests {
+    use super::*;
+    use lexer::lex;
+
+    /// Marks `source` and writes the result the way the module documentation does
+    fn mark(source: &str) -> Result<String, IndentationError> {
+        let lexemes = mark_blocks(source, lex(source).unwrap())?;
+        let texts: Vec<&str> = lexemes.iter().map(|lexeme| match lexeme.kind {
+            LexemeKind::Newline => "⏎",
+            LexemeKind::Indent => "→",
+            LexemeKind::Dedent => "←",
+            _ => lexeme.text.as_str(),
+        }).collect();
+        Ok(texts.join(" "))
+    }
+
+    #[test]
+    fn test_mark_blocks() {
+        let result = mark("if x > 5:\n    y = 1\n\n    z = 2\nw = 3\n");
+        assert_eq!(result, Ok("if x > 5 : ⏎ → y = 1 ⏎ z = 2 ⏎ ← w = 3 ⏎".to_string()));
+    }
+
+    #[test]
+    fn test_mark_nested_blocks() {
+        let result = mark("def f():\n\twhile x:\n\t\tx = 1").unwrap();
+        assert_eq!(result, "def f ( ) : ⏎ → while x : ⏎ → x = 1 ⏎ ← ←");
+    }
+
+    #[test]
+    fn test_brackets_continue_lines() {
+        let result = mark("x = [1,\n     2]\ny = 3\n").unwrap();
+        assert_eq!(result, "x = [ 1 , 2 ] ⏎ y = 3 ⏎");
+    }
+
+    #[test]
+    fn test_comment_lines_are_blank() {
+        let result = mark("if x:\n# not a block\n    y = 1\n").unwrap();
+        assert_eq!(result, "if x : ⏎ → y = 1 ⏎ ←");
+    }
+
+    #[test]
+    fn test_markers_point_at_their_line() {
+        let source = "if x:\n    y = 1\nz = 2\n";
+        let lexemes = mark_blocks(source, lex(source).unwrap()).unwrap();
+        let dedent = lexemes.iter().find(|lexeme| lexeme.kind == LexemeKind::Dedent).unwrap();
+        assert_eq!(dedent.span, Span{ start: 16, end: 16, line: 3, column: 1 });
+    }
+
+    #[test]
+    fn test_newline_added_at_end_of_source() {
+        let source = "x = 1\ny = 2";
+        let lexemes = mark_blocks(source, lex(source).unwrap()).unwrap();
+        let newline = lexemes.last().unwrap();
+        assert_eq!(newline.kind, LexemeKind::Newline);
+        assert_eq!(newline.span, Span{ start: 11, end: 11, line: 2, column: 6 });
+    }
+
+    #[test]
+    fn test_unexpected_indent() {
+        let result = mark("x = 1\n    y = 2\n");
+        assert_eq!(result, Err(IndentationError{ line: 2, column: 5, kind: IndentationErrorKind::UnexpectedIndent }));
+    }
+
+    #[test]
+    fn test_expected_indented_block() {
+        let result = mark("if x:\ny = 2\n");
+        let kind = IndentationErrorKind::ExpectedIndentedBlock{ opened_on: 1 };
+        assert_eq!(result, Err(IndentationError{ line: 2, column: 1, kind }));
+        assert!(mark("while x:\n").is_err());
+        assert!(mark("while x:").is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_indentation() {
+        let result = mark("if x:\n    y = 1\n  z = 2\n");
+        assert_eq!(result, Err(IndentationError{ line: 3, column: 3, kind: IndentationErrorKind::InconsistentIndentation }));
+    }
+
+    #[test]
+    fn test_mixed_tabs_and_spaces() {
+        let result = mark("if x:\n\ty = 1\n    z = 2\n");
+        assert_eq!(result, Err(IndentationError{ line: 3, column: 5, kind: IndentationErrorKind::InconsistentIndentation }));
+    }
+}
@@ -0,0 +1,781 @@
+//! Contains the lexer, which turns source into the `Lexeme`s the parsers work on, and the
+//! parsers that match a single `Lexeme`.
+//!
+//! Every `Lexeme` knows where it came from in the source, so anything built out of them can
+//! be traced back to a line and column:
+//!
+//! ```
+//! x = 10  # ten
+//! ```
+//!
+//! becomes an identifier, a `=` symbol, an integer and a newline. Spaces and comments don't
+//! become `Lexeme`s. Indentation doesn't either, until `indentation::mark_blocks` looks at it.
+use std::error::Error;
+use std::fmt;
+
+use nom::*;
+use nom::types::CompleteStr;
+#[cfg(feature = "unicode-identifiers")]
+use unicode_xid::UnicodeXID;
+
+/// The words that can't be used as identifiers. A word is only a keyword if all of it matches,
+/// so `iffy` and `definition` are identifiers. The parsers match these with `keyword`.
+pub const KEYWORDS: &[&str] = &[
+    "def", "return", "if", "elif", "else", "while", "for", "in", "and", "or", "not",
+    "True", "False", "None",
+];
+
+/// Operators and punctuation. Longer symbols come first, so `<=` isn't lexed as `<` and `=`.
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "==", "!=", "**", "//", "<<", ">>",
+    "+", "-", "*", "/", "%", "<", ">", "=", "&", "|", "^", "~",
+    "(", ")", "[", "]", "{", "}", ",", ":", ";",
+];
+
+/// Where a `Lexeme` is in the source. `start` and `end` are byte offsets, `line` and
+/// `column` are 1-based and point at the first character.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexemeKind {
+    Keyword,
+    Identifier,
+    Integer,
+    Float,
+    Str,
+    /// An operator or a bracket, comma, colon or semicolon
+    Symbol,
+    Newline,
+    /// Added by `indentation::mark_blocks` where a block starts
+    Indent,
+    /// Added by `indentation::mark_blocks` where a block ends
+    Dedent,
+}
+
+/// A piece of source with its kind and position. `text` is what the source says, so a string
+/// still has its quotes and escapes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme {
+    pub kind: LexemeKind,
+    pub text: String,
+    pub span: Span,
+}
+
+impl Lexeme {
+    /// Creates a `Lexeme` that takes up no source, such as an `Indent`, at `span`
+    pub fn marker(kind: LexemeKind, span: Span) -> Lexeme {
+        Lexeme {
+            kind,
+            text: String::new(),
+            span: Span{ end: span.start, ..span },
+        }
+    }
+}
+
+/// The input of the parsers: the `Lexeme`s that haven't been parsed yet. Like `CompleteStr`,
+/// it tells nom that no more input is coming.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lexemes<'a> {
+    pub lexemes: &'a [Lexeme],
+}
+
+impl<'a> Lexemes<'a> {
+    pub fn new(lexemes: &'a [Lexeme]) -> Lexemes<'a> {
+        Lexemes { lexemes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lexemes.is_empty()
+    }
+
+    /// Returns the next `Lexeme`, if there is one
+    pub fn first(&self) -> Option<&'a Lexeme> {
+        self.lexemes.first()
+    }
+
+    fn advance(&self, count: usize) -> Lexemes<'a> {
+        Lexemes { lexemes: &self.lexemes[count..] }
+    }
+
+    /// Returns the `Span` from the first of these `Lexeme`s to the last one before `rest`.
+    /// Markers at the end, such as the `Newline` after a statement, are left out.
+    pub fn span_to(&self, rest: Lexemes<'a>) -> Span {
+        let consumed = &self.lexemes[..self.lexemes.len() - rest.lexemes.len()];
+        let first = match consumed.first() {
+            Some(first) => first.span,
+            None => return Span::default(),
+        };
+        let last = consumed.iter()
+            .rev()
+            .find(|lexeme| !lexeme.text.is_empty() && lexeme.kind != LexemeKind::Newline)
+            .map_or(first, |last| last.span);
+        Span{ end: last.end.max(first.start), ..first }
+    }
+}
+
+impl<'a> InputLength for Lexemes<'a> {
+    fn input_len(&self) -> usize {
+        self.lexemes.len()
+    }
+}
+
+impl<'a> AtEof for Lexemes<'a> {
+    fn at_eof(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the input without consuming any of it, so a parser can tell where it started
+pub fn position(input: Lexemes) -> IResult<Lexemes, Lexemes> {
+    Ok((input, input))
+}
+
+/// Matches the next `Lexeme` if it is of `kind`
+pub fn lexeme<'a>(input: Lexemes<'a>, kind: LexemeKind) -> IResult<Lexemes<'a>, &'a Lexeme> {
+    match input.first() {
+        Some(next) if next.kind == kind => Ok((input.advance(1), next)),
+        _ => Err(Err::Error(error_position!(input, ErrorKind::Tag))),
+    }
+}
+
+/// Matches the keyword `word`, which has to be in `KEYWORDS`
+pub fn keyword<'a>(input: Lexemes<'a>, word: &'static str) -> IResult<Lexemes<'a>, &'a Lexeme> {
+    debug_assert!(is_keyword(word), "`{}` isn't in KEYWORDS", word);
+    match input.first() {
+        Some(next) if next.kind == LexemeKind::Keyword && next.text == word => Ok((input.advance(1), next)),
+        _ => Err(Err::Error(error_position!(input, ErrorKind::Tag))),
+    }
+}
+
+/// Matches the operator or punctuation `text`
+pub fn symbol<'a>(input: Lexemes<'a>, text: &'static str) -> IResult<Lexemes<'a>, &'a Lexeme> {
+    match input.first() {
+        Some(next) if next.kind == LexemeKind::Symbol && next.text == text => Ok((input.advance(1), next)),
+        _ => Err(Err::Error(error_position!(input, ErrorKind::Tag))),
+    }
+}
+
+/// Takes every `Lexeme` up to the symbol `text`, without consuming it
+pub fn until_symbol<'a>(input: Lexemes<'a>, text: &'static str) -> IResult<Lexemes<'a>, Lexemes<'a>> {
+    let position = input.lexemes.iter().position(|next| next.kind == LexemeKind::Symbol && next.text == text);
+    match position {
+        Some(count) => Ok((input.advance(count), Lexemes { lexemes: &input.lexemes[..count] })),
+        None => Err(Err::Error(error_position!(input, ErrorKind::TakeUntil))),
+    }
+}
+
+/// Source that can't be split into `Lexeme`s
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: LexErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    /// A string that isn't closed before the end of its line, or of the source if its quotes are
+    /// tripled
+    UnterminatedString,
+    /// A backslash in a string that isn't followed by an escape sequence. Holds the text of it.
+    InvalidEscape(String),
+    /// A number with a digit its base doesn't have, or an `_` that isn't between two digits
+    InvalidNumber(String),
+    /// An integer that doesn't fit in 32 bits, or a float too large to hold
+    NumberTooLarge(String),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            LexErrorKind::InvalidEscape(ref text) => write!(f, "invalid escape sequence `{}`", text),
+            LexErrorKind::InvalidNumber(ref text) => write!(f, "invalid number `{}`", text),
+            LexErrorKind::NumberTooLarge(ref text) => write!(f, "number `{}` is too large", text),
+        }
+    }
+}
+
+impl Error for LexError {
+    fn description(&self) -> &str {
+        match self.kind {
+            LexErrorKind::UnexpectedCharacter(_) => "Unexpected character",
+            LexErrorKind::UnterminatedString => "Unterminated string",
+            LexErrorKind::InvalidEscape(_) => "Invalid escape sequence",
+            LexErrorKind::InvalidNumber(_) => "Invalid number",
+            LexErrorKind::NumberTooLarge(_) => "Number is too large",
+        }
+    }
+}
+
+named!(blank<CompleteStr, CompleteStr>,
+    recognize!(
+        many0!(
+            alt!(
+                is_a!(" \t\r") |
+                preceded!(char!('#'), take_while!(|c| c != '\n'))
+            )
+        )
+    )
+);
+
+named!(newline<CompleteStr, (LexemeKind, CompleteStr)>,
+    do_parse!(
+        text: tag!("\n") >>
+        (LexemeKind::Newline, text)
+    )
+);
+
+/// Matches a number, such as `42`, `1_000`, `0xFF`, `0o17`, `0b1010`, `1.5`, `.5` or `1e-9`. The
+/// letters and digits right after it are taken as part of it, so `0b102` and `12abc` are
+/// reported as bad numbers by `number_value` rather than split in two.
+fn number(input: CompleteStr) -> IResult<CompleteStr, (LexemeKind, CompleteStr)> {
+    let bytes = input.as_bytes();
+    let is_number = match bytes.first() {
+        Some(b'.') => bytes.get(1).is_some_and(u8::is_ascii_digit),
+        Some(first) => first.is_ascii_digit(),
+        None => false,
+    };
+    if !is_number {
+        return Err(Err::Error(error_position!(input, ErrorKind::Digit)));
+    }
+    let prefixed = bytes.len() > 1 && bytes[0] == b'0' && b"xXoObB".contains(&bytes[1]);
+    let mut end = 0;
+    let mut seen_point = false;
+    while let Some(&b) = bytes.get(end) {
+        let exponent_sign = (b == b'+' || b == b'-') && !prefixed && (bytes[end - 1] == b'e' || bytes[end - 1] == b'E');
+        if b == b'.' && !prefixed && !seen_point {
+            seen_point = true;
+        } else if !(b.is_ascii_alphanumeric() || b == b'_' || exponent_sign) {
+            break;
+        }
+        end += 1;
+    }
+    let text = &input[..end];
+    let is_float = !prefixed && text.contains(['.', 'e', 'E']);
+    let kind = if is_float { LexemeKind::Float } else { LexemeKind::Integer };
+    Ok((CompleteStr(&input[end..]), (kind, CompleteStr(text))))
+}
+
+/// The value of a number `Lexeme`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+/// The magnitude of the smallest value a register holds, `i32::MIN`. A decimal number this large
+/// only fits in a register once it is negated, so `lex` only accepts it right after a `-`.
+const NEGATED_LIMIT: u64 = 1 << 31;
+
+/// Returns the value of the text of a number `Lexeme`. An `_` can go between any two digits.
+/// Registers hold 32 bits. A hex, octal or binary integer can be any 32 bit pattern, up to
+/// `0xFFFF_FFFF`, and anything above `0x7FFF_FFFF` is negative once it is in a register. A
+/// decimal number is a value rather than a pattern, so it can be at most 2147483647, or
+/// 2147483648 for `-2147483648`. Like in Python, a decimal integer can't start with a `0`
+/// unless it is all zeros, since `007` looks like an octal number.
+pub fn number_value(text: &str) -> Result<Number, LexErrorKind> {
+    let invalid = || LexErrorKind::InvalidNumber(text.to_string());
+    let (radix, digits) = match text.get(..2) {
+        Some("0x") | Some("0X") => (16, &text[2..]),
+        Some("0o") | Some("0O") => (8, &text[2..]),
+        Some("0b") | Some("0B") => (2, &text[2..]),
+        _ => (10, text),
+    };
+    // After a prefix, an `_` may also come first, as in `0x_FF`
+    let chars: Vec<char> = digits.chars().collect();
+    for (index, &c) in chars.iter().enumerate() {
+        if c != '_' {
+            continue;
+        }
+        let after_digit = if index == 0 { radix != 10 } else { chars[index - 1].is_digit(radix) };
+        let before_digit = chars.get(index + 1).is_some_and(|next| next.is_digit(radix));
+        if !after_digit || !before_digit {
+            return Err(invalid());
+        }
+    }
+    let cleaned: String = chars.into_iter().filter(|&c| c != '_').collect();
+
+    if radix == 10 && cleaned.contains(['.', 'e', 'E']) {
+        return match cleaned.parse::<f64>() {
+            Ok(value) if value > NEGATED_LIMIT as f64 => Err(LexErrorKind::NumberTooLarge(text.to_string())),
+            Ok(value) => Ok(Number::Float(value)),
+            Err(_) => Err(invalid()),
+        };
+    }
+    if cleaned.is_empty() || !cleaned.chars().all(|c| c.is_digit(radix)) {
+        return Err(invalid());
+    }
+    if radix == 10 && cleaned.starts_with('0') && cleaned.contains(|c| c != '0') {
+        return Err(invalid());
+    }
+    let limit = if radix == 10 { NEGATED_LIMIT } else { u64::from(u32::MAX) };
+    match u64::from_str_radix(&cleaned, radix) {
+        Ok(value) if value <= limit => Ok(Number::Integer(value as i64)),
+        _ => Err(LexErrorKind::NumberTooLarge(text.to_string())),
+    }
+}
+
+/// Matches an identifier or a keyword: a letter or `_`, then any number of letters, digits and
+/// `_`s, like `tile_count`, `_private` or `maxHP2`
+fn name(input: CompleteStr) -> IResult<CompleteStr, (LexemeKind, CompleteStr)> {
+    let mut chars = input.char_indices();
+    match chars.next() {
+        Some((_, c)) if is_name_start(c) => {},
+        _ => return Err(Err::Error(error_position!(input, ErrorKind::Alpha))),
+    }
+    let end = chars.find(|&(_, c)| !is_name_continue(c)).map_or(input.len(), |(index, _)| index);
+    let text = &input[..end];
+    let kind = if is_keyword(text) { LexemeKind::Keyword } else { LexemeKind::Identifier };
+    Ok((CompleteStr(&input[end..]), (kind, CompleteStr(text))))
+}
+
+/// Returns whether `c` can start an identifier
+#[cfg(not(feature = "unicode-identifiers"))]
+fn is_name_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+/// Returns whether `c` can be in an identifier after its first character
+#[cfg(not(feature = "unicode-identifiers"))]
+fn is_name_continue(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+/// Returns whether `c` can start an identifier. With the `unicode-identifiers` feature, that is
+/// `_` or any character with the Unicode `XID_Start` property, as in Python.
+#[cfg(feature = "unicode-identifiers")]
+fn is_name_start(c: char) -> bool {
+    c == '_' || UnicodeXID::is_xid_start(c)
+}
+
+/// Returns whether `c` can be in an identifier after its first character. With the
+/// `unicode-identifiers` feature, that is any character with the Unicode `XID_Continue`
+/// property, which takes in digits and `_`.
+#[cfg(feature = "unicode-identifiers")]
+fn is_name_continue(c: char) -> bool {
+    UnicodeXID::is_xid_continue(c)
+}
+
+/// Returns whether `word` is one of the `KEYWORDS`
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word)
+}
+
+/// Matches a string in single or double quotes. A backslash escapes the character after it.
+/// A string in tripled quotes, like `"""this"""`, can span lines.
+fn string(input: CompleteStr) -> IResult<CompleteStr, (LexemeKind, CompleteStr)> {
+    if !input.starts_with('"') && !input.starts_with('\'') {
+        return Err(Err::Error(error_position!(input, ErrorKind::Char)));
+    }
+    let quotes = quote_length(&input);
+    let closing = &input[..quotes];
+    let mut escaped = false;
+    for (index, c) in input.char_indices().skip(quotes) {
+        match c {
+            '\n' if quotes == 1 => break,
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if input[index..].starts_with(closing) => {
+                let end = index + closing.len();
+                return Ok((CompleteStr(&input[end..]), (LexemeKind::Str, CompleteStr(&input[..end]))));
+            },
+            _ => {},
+        }
+    }
+    Err(Err::Failure(error_position!(input, ErrorKind::Escaped)))
+}
+
+/// How many quotes a string starts with: 3 for a triple quoted string, otherwise 1
+fn quote_length(text: &str) -> usize {
+    if text.starts_with("\"\"\"") || text.starts_with("'''") {
+        3
+    } else {
+        1
+    }
+}
+
+/// Returns the characters the text of a string `Lexeme` stands for. The quotes are left out and
+/// the escape sequences `\n`, `\t`, `\r`, `\0`, `\"`, `\'`, `\\` and `\u{...}` are replaced.
+/// A bad escape sequence is returned along with how many characters into `text` it starts.
+pub fn string_value(text: &str) -> Result<String, (usize, LexErrorKind)> {
+    let quotes = quote_length(text);
+    let chars: Vec<char> = text.chars().collect();
+    let body = &chars[quotes..chars.len() - quotes];
+    let mut value = String::new();
+    let mut index = 0;
+    while index < body.len() {
+        if body[index] != '\\' {
+            value.push(body[index]);
+            index += 1;
+            continue;
+        }
+        let escaped = match body.get(index + 1) {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('0') => Some('\0'),
+            Some(&c) if c == '"' || c == '\'' || c == '\\' => Some(c),
+            _ => None,
+        };
+        if let Some(c) = escaped {
+            value.push(c);
+            index += 2;
+            continue;
+        }
+        // `\u{...}` runs to the `}`. Anything else is a backslash and one character.
+        let length = match body.get(index + 1) {
+            Some('u') if body.get(index + 2) == Some(&'{') => {
+                body[index..].iter().position(|&c| c == '}').map_or(body.len() - index, |end| end + 1)
+            },
+            Some(_) => 2,
+            None => 1,
+        };
+        let escape: String = body[index..index + length].iter().collect();
+        // At most six hex digits between `\u{` and `}`
+        let c = if escape.starts_with("\\u{") && escape.ends_with('}') && length <= 10 {
+            u32::from_str_radix(&escape[3..escape.len() - 1], 16).ok().and_then(::std::char::from_u32)
+        } else {
+            None
+        };
+        match c {
+            Some(c) => value.push(c),
+            None => return Err((index + quotes, LexErrorKind::InvalidEscape(escape))),
+        }
+        index += length;
+    }
+    Ok(value)
+}
+
+fn symbol_text(input: CompleteStr) -> IResult<CompleteStr, (LexemeKind, CompleteStr)> {
+    for text in SYMBOLS {
+        if let Some(rest) = input.strip_prefix(text) {
+            return Ok((CompleteStr(rest), (LexemeKind::Symbol, CompleteStr(&input[..text.len()]))));
+        }
+    }
+    Err(Err::Error(error_position!(input, ErrorKind::Tag)))
+}
+
+named!(next_lexeme<CompleteStr, (LexemeKind, CompleteStr)>,
+    alt!(
+        newline |
+        number |
+        name |
+        string |
+        symbol_text
+    )
+);
+
+/// Splits `source` into `Lexeme`s. Every line break is a `Newline`, even on blank lines and
+/// inside brackets.
+pub fn lex(source: &str) -> Result<Vec<Lexeme>, LexError> {
+    let mut lexemes = vec![];
+    let mut input = CompleteStr(source);
+    let mut line = 1;
+    let mut column = 1;
+
+    loop {
+        let (rest, skipped) = blank(input).unwrap_or((input, CompleteStr("")));
+        column += skipped.chars().count();
+        input = rest;
+        if input.is_empty() {
+            return Ok(lexemes);
+        }
+
+        let start = source.len() - input.len();
+        let (rest, (kind, text)) = match next_lexeme(input) {
+            Ok(result) => result,
+            Err(e) => {
+                let kind = match e {
+                    Err::Failure(_) => LexErrorKind::UnterminatedString,
+                    _ => LexErrorKind::UnexpectedCharacter(input.chars().next().unwrap_or_default()),
+                };
+                return Err(LexError{ line, column, kind });
+            }
+        };
+        if kind == LexemeKind::Str {
+            if let Err((offset, kind)) = string_value(&text) {
+                let before: String = text.chars().take(offset).collect();
+                let (line, column) = position_after(line, column, &before);
+                return Err(LexError{ line, column, kind });
+            }
+        }
+        if kind == LexemeKind::Integer || kind == LexemeKind::Float {
+            if let Err(kind) = number_value(&text) {
+                return Err(LexError{ line, column, kind });
+            }
+            if is_negated_limit(&text) && !after_prefix_minus(&lexemes) {
+                return Err(LexError{ line, column, kind: LexErrorKind::NumberTooLarge(text.to_string()) });
+            }
+        }
+        let span = Span{ start, end: start + text.len(), line, column };
+        lexemes.push(Lexeme{ kind, text: text.to_string(), span });
+        input = rest;
+
+        let (next_line, next_column) = position_after(line, column, &text);
+        line = next_line;
+        column = next_column;
+    }
+}
+
+/// Checks if `text` is a decimal number that is `NEGATED_LIMIT`, which has to be negated
+fn is_negated_limit(text: &str) -> bool {
+    let decimal = !text.starts_with("0x") && !text.starts_with("0X") && !text.starts_with("0o") &&
+        !text.starts_with("0O") && !text.starts_with("0b") && !text.starts_with("0B");
+    let limit = match number_value(text) {
+        Ok(Number::Integer(value)) => value == NEGATED_LIMIT as i64,
+        Ok(Number::Float(value)) => value == NEGATED_LIMIT as f64,
+        Err(_) => false,
+    };
+    decimal && limit
+}
+
+/// Checks if the last of `lexemes` is a `-` in front of an operand, rather than between two
+fn after_prefix_minus(lexemes: &[Lexeme]) -> bool {
+    match lexemes.split_last() {
+        Some((minus, before)) if minus.kind == LexemeKind::Symbol && minus.text == "-" => {
+            !before.last().is_some_and(ends_operand)
+        },
+        _ => false,
+    }
+}
+
+/// Checks if `lexeme` can be the last one of an operand, like a name or a `)`
+fn ends_operand(lexeme: &Lexeme) -> bool {
+    match lexeme.kind {
+        LexemeKind::Identifier | LexemeKind::Integer | LexemeKind::Float | LexemeKind::Str => true,
+        LexemeKind::Symbol => lexeme.text == ")" || lexeme.text == "]",
+        LexemeKind::Keyword => ["True", "False", "None"].contains(&lexeme.text.as_str()),
+        _ => false,
+    }
+}
+
+/// Returns the line and column just after `text`, if it starts at `line` and `column`
+fn position_after(line: usize, column: usize, text: &str) -> (usize, usize) {
+    match text.rfind('\n') {
+        Some(last) => (line + text.matches('\n').count(), text[last + 1..].chars().count() + 1),
+        None => (line, column + text.chars().count()),
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<LexemeKind> {
+        lex(source).unwrap().iter().map(|lexeme| lexeme.kind).collect()
+    }
+
+    #[test]
+    fn test_lex_assignment() {
+        let lexemes = lex("x = 10").unwrap();
+        let texts: Vec<&str> = lexemes.iter().map(|lexeme| lexeme.text.as_str()).collect();
+        assert_eq!(texts, vec!["x", "=", "10"]);
+        assert_eq!(lexemes[2].kind, LexemeKind::Integer);
+        assert_eq!(lexemes[2].span, Span{ start: 4, end: 6, line: 1, column: 5 });
+    }
+
+    #[test]
+    fn test_lex_spans_across_lines() {
+        let lexemes = lex("if x:\n    y = 1.5\n").unwrap();
+        let y = &lexemes[4];
+        assert_eq!(y.text, "y");
+        assert_eq!(y.span, Span{ start: 10, end: 11, line: 2, column: 5 });
+        let float = &lexemes[6];
+        assert_eq!(float.kind, LexemeKind::Float);
+        assert_eq!((float.span.line, float.span.column), (2, 9));
+    }
+
+    #[test]
+    fn test_lex_keywords_and_identifiers() {
+        use self::LexemeKind::*;
+        assert_eq!(kinds("for format in inputs"), vec![Keyword, Identifier, Keyword, Identifier]);
+        assert_eq!(kinds("not x and y"), vec![Keyword, Identifier, Keyword, Identifier]);
+        assert_eq!(kinds("True False None Truth"), vec![Keyword, Keyword, Keyword, Identifier]);
+        // A word that only starts or ends with a keyword is an identifier
+        for word in KEYWORDS {
+            assert_eq!(kinds(word), vec![Keyword], "{}", word);
+            assert_eq!(kinds(&format!("{}2", word)), vec![Identifier], "{}", word);
+            assert_eq!(kinds(&format!("{}x", word)), vec![Identifier], "{}", word);
+            assert_eq!(kinds(&format!("x{}", word)), vec![Identifier], "{}", word);
+        }
+    }
+
+    #[test]
+    fn test_lex_operators() {
+        let lexemes = lex("a<=b==c>d").unwrap();
+        let texts: Vec<&str> = lexemes.iter().map(|lexeme| lexeme.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "<=", "b", "==", "c", ">", "d"]);
+        let lexemes = lex("a**b//c!=d*e/f%g").unwrap();
+        let texts: Vec<&str> = lexemes.iter().map(|lexeme| lexeme.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "**", "b", "//", "c", "!=", "d", "*", "e", "/", "f", "%", "g"]);
+        let lexemes = lex("a<<b>>c&~d|e^f<g").unwrap();
+        let texts: Vec<&str> = lexemes.iter().map(|lexeme| lexeme.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "<<", "b", ">>", "c", "&", "~", "d", "|", "e", "^", "f", "<", "g"]);
+    }
+
+    #[test]
+    fn test_lex_strings() {
+        let lexemes = lex(r#"x = "a \"quoted\" word" + 'b'"#).unwrap();
+        assert_eq!(lexemes[2].kind, LexemeKind::Str);
+        assert_eq!(lexemes[2].text, r#""a \"quoted\" word""#);
+        assert_eq!(lexemes[4].text, "'b'");
+        let result = lex("x = \"open\ny = 1");
+        assert_eq!(result, Err(LexError{ line: 1, column: 5, kind: LexErrorKind::UnterminatedString }));
+    }
+
+    #[test]
+    fn test_lex_identifiers() {
+        let lexemes = lex("tile_count _private maxHP2 __init__ _ x_1_").unwrap();
+        let texts: Vec<(&str, LexemeKind)> = lexemes.iter().map(|lexeme| (lexeme.text.as_str(), lexeme.kind)).collect();
+        assert_eq!(texts, vec![
+            ("tile_count", LexemeKind::Identifier),
+            ("_private", LexemeKind::Identifier),
+            ("maxHP2", LexemeKind::Identifier),
+            ("__init__", LexemeKind::Identifier),
+            ("_", LexemeKind::Identifier),
+            ("x_1_", LexemeKind::Identifier),
+        ]);
+        assert_eq!(kinds("_if if_ _True"), vec![LexemeKind::Identifier; 3]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "unicode-identifiers"))]
+    fn test_lex_ascii_identifiers_only() {
+        let result = lex("gr\u{f6}\u{df}e = 1");
+        assert_eq!(result, Err(LexError{ line: 1, column: 3, kind: LexErrorKind::UnexpectedCharacter('\u{f6}') }));
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-identifiers")]
+    fn test_lex_unicode_identifiers() {
+        let lexemes = lex("gr\u{f6}\u{df}e = \u{5909}\u{6570}_2 + caf\u{e9}").unwrap();
+        let texts: Vec<&str> = lexemes.iter().filter(|lexeme| lexeme.kind == LexemeKind::Identifier).map(|lexeme| lexeme.text.as_str()).collect();
+        assert_eq!(texts, vec!["gr\u{f6}\u{df}e", "\u{5909}\u{6570}_2", "caf\u{e9}"]);
+        assert_eq!(lexemes[2].span.column, 9);
+        let result = lex("x = \u{2603}");
+        assert_eq!(result, Err(LexError{ line: 1, column: 5, kind: LexErrorKind::UnexpectedCharacter('\u{2603}') }));
+    }
+
+    #[test]
+    fn test_lex_triple_quoted_strings() {
+        let lexemes = lex("x = \"\"\"one\n'two' \"three\" \"\"\"\ny = '''it\\'s'''\n").unwrap();
+        assert_eq!(lexemes[2].text, "\"\"\"one\n'two' \"three\" \"\"\"");
+        assert_eq!(string_value(&lexemes[2].text), Ok("one\n'two' \"three\" ".to_string()));
+        assert_eq!((lexemes[4].span.line, lexemes[4].span.column), (3, 1));
+        assert_eq!(string_value(&lexemes[6].text), Ok("it's".to_string()));
+        let result = lex("x = '''open\n\ny = 1");
+        assert_eq!(result, Err(LexError{ line: 1, column: 5, kind: LexErrorKind::UnterminatedString }));
+        let result = lex("x = \"\"\"\nok \\q\"\"\"");
+        assert_eq!(result, Err(LexError{ line: 2, column: 4, kind: LexErrorKind::InvalidEscape("\\q".to_string()) }));
+    }
+
+    #[test]
+    fn test_lex_numbers() {
+        let lexemes = lex("0xFF 0o17 0b1010 1_000_000 1e9 .5 1.5e-3 2E+2 5.").unwrap();
+        let texts: Vec<(&str, LexemeKind)> = lexemes.iter().map(|lexeme| (lexeme.text.as_str(), lexeme.kind)).collect();
+        assert_eq!(texts, vec![
+            ("0xFF", LexemeKind::Integer),
+            ("0o17", LexemeKind::Integer),
+            ("0b1010", LexemeKind::Integer),
+            ("1_000_000", LexemeKind::Integer),
+            ("1e9", LexemeKind::Float),
+            (".5", LexemeKind::Float),
+            ("1.5e-3", LexemeKind::Float),
+            ("2E+2", LexemeKind::Float),
+            ("5.", LexemeKind::Float),
+        ]);
+        assert_eq!(kinds("2-1 0xE-1"), vec![LexemeKind::Integer, LexemeKind::Symbol, LexemeKind::Integer, LexemeKind::Integer, LexemeKind::Symbol, LexemeKind::Integer]);
+    }
+
+    #[test]
+    fn test_number_value() {
+        assert_eq!(number_value("0xff"), Ok(Number::Integer(255)));
+        assert_eq!(number_value("0O17"), Ok(Number::Integer(15)));
+        assert_eq!(number_value("0b1010"), Ok(Number::Integer(10)));
+        assert_eq!(number_value("0x_FF_FF"), Ok(Number::Integer(65535)));
+        assert_eq!(number_value("1_000_000"), Ok(Number::Integer(1_000_000)));
+        assert_eq!(number_value("0xFFFFFFFF"), Ok(Number::Integer(4_294_967_295)));
+        assert_eq!(number_value("1e9"), Ok(Number::Float(1e9)));
+        assert_eq!(number_value(".5"), Ok(Number::Float(0.5)));
+        assert_eq!(number_value("1_0.2_5e-1_0"), Ok(Number::Float(10.25e-10)));
+        for text in &["0b102", "0o8", "0x", "0xG", "12abc", "1__0", "1_", "1_.5", "1._5", "1e", "1e_5", "0x__1", "09", "007", "0_1"] {
+            assert_eq!(number_value(text), Err(LexErrorKind::InvalidNumber(text.to_string())), "{}", text);
+        }
+        assert_eq!(number_value("2147483647"), Ok(Number::Integer(2_147_483_647)));
+        assert_eq!(number_value("00"), Ok(Number::Integer(0)));
+        assert_eq!(number_value("0_0"), Ok(Number::Integer(0)));
+        assert_eq!(number_value("0x007"), Ok(Number::Integer(7)));
+        assert_eq!(number_value("007.5"), Ok(Number::Float(7.5)));
+        assert_eq!(number_value("0x8000_0000"), Ok(Number::Integer(2_147_483_648)));
+        for text in &["2147483649", "3000000000", "4294967296", "0x1_0000_0000", "99999999999999999999999", "3e9", "2147483649.0", "1e999"] {
+            assert_eq!(number_value(text), Err(LexErrorKind::NumberTooLarge(text.to_string())), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_lex_invalid_numbers() {
+        let result = lex("x = 1\ny = 0b102 + 1");
+        assert_eq!(result, Err(LexError{ line: 2, column: 5, kind: LexErrorKind::InvalidNumber("0b102".to_string()) }));
+        let result = lex("x = 4294967296");
+        assert_eq!(result, Err(LexError{ line: 1, column: 5, kind: LexErrorKind::NumberTooLarge("4294967296".to_string()) }));
+    }
+
+    #[test]
+    fn test_negated_limit_needs_minus() {
+        for source in &["-2147483648", "x = -2147483648", "x = 1 * -2147483648.0", "f(-2147483648)", "x = 1\n-2147483648"] {
+            assert!(lex(source).is_ok(), "{}", source);
+        }
+        for source in &["x = 2147483648", "x = 1 -2147483648", "x = (2147483648)", "x = y - 2147483648", "x = -(2147483648)"] {
+            match lex(source) {
+                Err(LexError{ kind: LexErrorKind::NumberTooLarge(_), .. }) => {},
+                other => panic!("Expected {} to be too large, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_value() {
+        assert_eq!(string_value(r#""plain""#), Ok("plain".to_string()));
+        assert_eq!(string_value(r#"'a\tb\nc'"#), Ok("a\tb\nc".to_string()));
+        assert_eq!(string_value(r#""say \"hi\" \\ it's""#), Ok("say \"hi\" \\ it's".to_string()));
+        assert_eq!(string_value(r#"'\u{48}\u{e9}\u{1F600}'"#), Ok("H\u{e9}\u{1F600}".to_string()));
+        assert_eq!(string_value(r#""\q""#), Err((1, LexErrorKind::InvalidEscape("\\q".to_string()))));
+        assert_eq!(string_value(r#""ok\u{zz}""#), Err((3, LexErrorKind::InvalidEscape("\\u{zz}".to_string()))));
+        assert_eq!(string_value(r#""\u{D800}""#), Err((1, LexErrorKind::InvalidEscape("\\u{D800}".to_string()))));
+        assert_eq!(string_value(r#""\u{48""#), Err((1, LexErrorKind::InvalidEscape("\\u{48".to_string()))));
+    }
+
+    #[test]
+    fn test_lex_invalid_escape() {
+        let result = lex(r#"x = "tab\q""#);
+        assert_eq!(result, Err(LexError{ line: 1, column: 9, kind: LexErrorKind::InvalidEscape("\\q".to_string()) }));
+    }
+
+    #[test]
+    fn test_lex_comments_and_newlines() {
+        use self::LexemeKind::*;
+        assert_eq!(kinds("x = 1 # one\n\n# nothing\ny"), vec![Identifier, Symbol, Integer, Newline, Newline, Newline, Identifier]);
+        let lexemes = lex("# first\ny").unwrap();
+        assert_eq!((lexemes[1].span.line, lexemes[1].span.column), (2, 1));
+    }
+
+    #[test]
+    fn test_lex_unexpected_character() {
+        let result = lex("x = 1\ny = $2");
+        assert_eq!(result, Err(LexError{ line: 2, column: 5, kind: LexErrorKind::UnexpectedCharacter('$') }));
+    }
+}
@@ -8,72 +8,71 @@
 //! 
 
 use nom::*;
-use nom::types::CompleteStr;
 
 use tokens::Token;
 use expression_parsers::expression;
-use factor_parsers::{identifiers, identifier, factor, integer, float64};
+use factor_parsers::{identifier, factor, integer, float64};
+use lexer::{Lexemes, position, symbol};
+use parse_errors::Expected;
 
-/// Extracts everything between `[` and `]`
-named!(list_interior<CompleteStr, Vec<String>>, 
-    ws!(
-        do_parse!(
-            ws!(tag!("[")) >>
-            contents: take_until_and_consume!("]") >>
-            (
-                {
-                    let mut result: Vec<String> = vec![];
-                    let cloned = contents.to_string();
-                    let split = cloned.split(",");
-                    for s in split {
-                        let s = s.trim();
-                        result.push(s.to_string());
-                    }
-                    result
-                }
-            )
+/// Extracts the elements between `[` and `]`. The last one may be followed by a `,`, but `[,]`
+/// is an error.
+named!(list_interior<Lexemes, Vec<Token>>, 
+    do_parse!(
+        call!(symbol, "[") >>
+        elements: separated_list!(call!(symbol, ","), expression) >>
+        cond!(!elements.is_empty(), call!(symbol, ",")) >>
+        expect!(Expected::ClosingBracket, call!(symbol, "]")) >>
+        (
+            elements
         )
     )
 );
 
 /// Top level parser for a list
-named!(pub list<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            interior: list_interior >>
-            (
-                {
-                    let mut elements = vec![];
-                    for i in &interior {
-                        let result = expression(CompleteStr(i));
-                        let result = result.unwrap().1;
-                        elements.push(result);
-                    }
-                    Token::List{ elements: elements }
-                }
-            )
+named!(pub list<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        elements: list_interior >>
+        end: position >>
+        (
+            {
+                Token::List{ elements, span: start.span_to(end) }
+            }
         )
     )
 );
 
 mod tests {
     use super::*;
+    use lexer::lex;
 
     #[test]
     fn test_parse_empty_list() {
-        let result = list(CompleteStr("[]"));
+        let lexemes = lex("[]").unwrap();
+        let result = list(Lexemes::new(&lexemes));
         assert!(result.is_ok());
+        let lexemes = lex("[,]").unwrap();
+        match list(Lexemes::new(&lexemes)) {
+            Err(Err::Failure(_)) => {},
+            other => panic!("Expected a failure, got {:?}", other),
+        }
     }
 
     #[test]
     fn test_parse_list_with_literals_and_identifiers() {
-        let result = list(CompleteStr("[1, a]"));
+        let lexemes = lex("[1, a]").unwrap();
+        let result = list(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_parse_list_with_expression() {
-        let result = list(CompleteStr("[1, a, 1+2]"));
-        assert!(result.is_ok());
+        let lexemes = lex("[1, a, 1+2]").unwrap();
+        let result = list(Lexemes::new(&lexemes));
+        match result {
+            Ok((_, Token::List{ elements, .. })) => assert_eq!(elements.len(), 3),
+            other => panic!("Expected a list, got {:?}", other),
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,170 @@
+//! Contains the live range analysis the `Compiler` uses to give the registers of variables
+//! that are no longer needed back to the register allocator
+use std::collections::HashMap;
+
+use tokens::Token;
+
+/// Finds, for each statement in `statements`, the variables that are mentioned for the last
+/// time in that statement. Their registers can be reused once the statement is compiled.
+///
+/// Variables mentioned in `live_out`, such as the return statement that follows a function
+/// body, are never in the result. Neither are variables mentioned inside a function
+/// definition, since the function can be called after any of the statements.
+pub fn last_uses(statements: &[Token], live_out: &[&Token]) -> Vec<Vec<String>> {
+    let mut pinned = vec![];
+    for token in live_out {
+        let mut names = vec![];
+        mentions(token, &mut names, &mut pinned);
+        pinned.extend(names);
+    }
+
+    let mut last_use: HashMap<String, usize> = HashMap::new();
+    for (index, statement) in statements.iter().enumerate() {
+        let mut names = vec![];
+        mentions(statement, &mut names, &mut pinned);
+        for name in names {
+            last_use.insert(name, index);
+        }
+    }
+
+    let mut result = vec![vec![]; statements.len()];
+    for (name, index) in last_use {
+        if !pinned.contains(&name) {
+            result[index].push(name);
+        }
+    }
+    result
+}
+
+/// The names of the variables `statement` mentions, leaving out the ones only mentioned inside
+/// a function definition
+pub fn mentioned_names(statement: &Token) -> Vec<String> {
+    let mut names = vec![];
+    mentions(statement, &mut names, &mut vec![]);
+    names
+}
+
+/// Collects the names of the variables `node` mentions. Names mentioned inside a function
+/// definition go in `pinned` instead.
+fn mentions(node: &Token, names: &mut Vec<String>, pinned: &mut Vec<String>) {
+    match *node {
+        Token::Identifier{ ref name, .. } => {
+            names.push(name.to_string());
+        },
+        Token::Factor{ ref value, .. } => {
+            mentions(value, names, pinned);
+        },
+        Token::BinaryOperation{ ref left, ref right, .. } => {
+            mentions(left, names, pinned);
+            mentions(right, names, pinned);
+        },
+        Token::UnaryOperation{ ref operand, .. } => {
+            mentions(operand, names, pinned);
+        },
+        Token::If{ ref expr, ref body, .. } | Token::Elif{ ref expr, ref body, .. } => {
+            mentions(expr, names, pinned);
+            for statement in body {
+                mentions(statement, names, pinned);
+            }
+        },
+        Token::Else{ ref body, .. } => {
+            for statement in body {
+                mentions(statement, names, pinned);
+            }
+        },
+        Token::Conditional{ ref branches, .. } => {
+            for branch in branches {
+                mentions(branch, names, pinned);
+            }
+        },
+        Token::List{ ref elements, .. } | Token::Tuple{ ref elements, .. } => {
+            for element in elements {
+                mentions(element, names, pinned);
+            }
+        },
+        Token::Dictionary{ ref keys, ref values, .. } => {
+            for token in keys.iter().chain(values) {
+                mentions(token, names, pinned);
+            }
+        },
+        Token::DictionaryKeyValuePair{ ref key, ref value, .. } => {
+            mentions(key, names, pinned);
+            mentions(value, names, pinned);
+        },
+        Token::FunctionCall{ ref arguments, .. } => {
+            mentions(arguments, names, pinned);
+        },
+        Token::Arguments{ ref values, .. } => {
+            for value in values {
+                mentions(value, names, pinned);
+            }
+        },
+        Token::Parameters{ names: ref parameter_names, .. } => {
+            names.extend(parameter_names.iter().cloned());
+        },
+        Token::Function{ ref parameters, ref body, .. } => {
+            let mut inner = vec![];
+            mentions(parameters, &mut inner, pinned);
+            mentions(body, &mut inner, pinned);
+            pinned.extend(inner);
+        },
+        Token::FunctionBody{ ref expressions, .. } |
+        Token::WhileLoopBody{ ref expressions, .. } |
+        Token::ForLoopBody{ ref expressions, .. } |
+        Token::Program{ ref expressions, .. } => {
+            for statement in expressions {
+                mentions(statement, names, pinned);
+            }
+        },
+        Token::ReturnStatement{ ref parameters, .. } => {
+            mentions(parameters, names, pinned);
+        },
+        Token::WhileLoop{ ref start, ref body, .. } | Token::ForLoop{ ref start, ref body, .. } => {
+            mentions(start, names, pinned);
+            mentions(body, names, pinned);
+        },
+        Token::WhileLoopStart{ ref expression, .. } => {
+            mentions(expression, names, pinned);
+        },
+        Token::ForLoopStart{ ref variable_name, ref collection_name, .. } => {
+            names.push(variable_name.to_string());
+            names.push(collection_name.to_string());
+        },
+        _ => {},
+    }
+}
+
+mod tests {
+    use super::*;
+    use lexer::Span;
+    use program_parsers::parse_source;
+
+    fn statements(source: &str) -> Vec<Token> {
+        match parse_source(source) {
+            Ok(Token::Program{ expressions, .. }) => expressions,
+            other => panic!("Unable to parse {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_last_uses() {
+        let statements = statements("x = 1\ny = x + 1\ny * 2");
+        let result = last_uses(&statements, &[]);
+        assert_eq!(result, vec![vec![], vec!["x".to_string()], vec!["y".to_string()]]);
+    }
+
+    #[test]
+    fn test_last_uses_skips_live_out() {
+        let statements = statements("x = 1\ny = 2");
+        let live_out = Token::Identifier{ name: "x".into(), span: Span::default() };
+        let result = last_uses(&statements, &[&live_out]);
+        assert_eq!(result, vec![Vec::<String>::new(), vec!["y".to_string()]]);
+    }
+
+    #[test]
+    fn test_last_uses_skips_variables_used_in_functions() {
+        let statements = statements("x = 1\ndef main():\n    return x;\n");
+        let result = last_uses(&statements, &[]);
+        assert!(result.iter().all(|names| names.is_empty()));
+    }
+}
@@ -15,10 +15,13 @@
 //! until the expression is no longer True.
 
 use nom::*;
-use nom::types::CompleteStr;
 
 use tokens::Token;
+use factor_parsers::identifier;
 use expression_parsers::expression;
+use lexer::{Lexemes, keyword, position, symbol};
+use parse_errors::Expected;
+use statement_parsers::body;
 
 /// Parses a for loop start
 /// 
@@ -30,41 +33,43 @@ use expression_parsers::expression;
 /// 
 /// `tile` must be a valid `Identifier`, and `tiles` must be a valid collection.
 /// 
-named!(pub for_loop_start<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            ws!(tag!("for")) >>
-            variable_name: ws!(alphanumeric) >>
-            ws!(tag!("in")) >>
-            collection_name: ws!(alphanumeric) >>
-            ws!(tag!(":")) >>
-            (
-                {
-                    Token::ForLoopStart{
-                        variable_name: variable_name.to_string(),
-                        collection_name: collection_name.to_string()
-                    }
+named!(pub for_loop_start<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "for") >>
+        variable_name: expect!(Expected::Identifier, identifier) >>
+        expect!(Expected::In, call!(keyword, "in")) >>
+        collection_name: expect!(Expected::Identifier, identifier) >>
+        expect!(Expected::Colon, call!(symbol, ":")) >>
+        end: position >>
+        (
+            {
+                Token::ForLoopStart{
+                    variable_name,
+                    collection_name,
+                    span: start.span_to(end)
                 }
-            )
-        ) 
+            }
+        )
     )
 );
 
 /// Parses the body of a for loop. The body contains an arbitrary number of
 /// expressions
-named!(pub for_loop_body<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            expressions: many0!(expression) >>
-            (
-                {
-                    Token::ForLoopBody{
-                        expressions: expressions
-                    }
+named!(pub for_loop_body<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        expressions: body >>
+        end: position >>
+        (
+            {
+                Token::ForLoopBody{
+                    expressions,
+                    span: start.span_to(end)
                 }
-            )
-        ) 
-    )
+            }
+        )
+    ) 
 );
 
 /// Higher level parse that looks for both the start of a for loop and its body
@@ -76,19 +81,20 @@ named!(pub for_loop_body<CompleteStr, Token>,
 ///     print(tile)
 /// ```
 /// 
-named!(pub for_loop<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            start: for_loop_start >>
-            body: for_loop_body >>
-            (
-                {
-                    Token::ForLoop{
-                        start: Box::new(start),
-                        body: Box::new(body)
-                    }
+named!(pub for_loop<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        head: for_loop_start >>
+        body: for_loop_body >>
+        end: position >>
+        (
+            {
+                Token::ForLoop{
+                    start: Box::new(head),
+                    body: Box::new(body),
+                    span: start.span_to(end)
                 }
-            )
+            }
         )
     )
 );
@@ -101,30 +107,30 @@ named!(pub for_loop<CompleteStr, Token>,
 /// while x < 1:
 /// ```
 /// 
-named!(pub while_loop_start<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            ws!(tag!("while")) >>
-            loop_expression: expression >>
-            tag!(":") >>
-            (
-                {
-                    Token::WhileLoopStart{ expression: Box::new(loop_expression) }
-                }
-            )
+named!(pub while_loop_start<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        call!(keyword, "while") >>
+        loop_expression: expect!(Expected::Expression, expression) >>
+        expect!(Expected::Colon, call!(symbol, ":")) >>
+        end: position >>
+        (
+            {
+                Token::WhileLoopStart{ expression: Box::new(loop_expression), span: start.span_to(end) }
+            }
         )
     )
 );
 
 /// Looks for a while loop body, which is an arbitrary number of expressions
-named!(pub while_loop_body<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            // This signals the beginning of the body
-            expressions: many0!(expression) >>
-            (
-                Token::WhileLoopBody{ expressions: expressions }
-            )
+named!(pub while_loop_body<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        // This signals the beginning of the body
+        expressions: body >>
+        end: position >>
+        (
+            Token::WhileLoopBody{ expressions, span: start.span_to(end) }
         )
     )
 );
@@ -138,52 +144,54 @@ named!(pub while_loop_body<CompleteStr, Token>,
 ///     x = x + 1
 /// ```
 ///
-named!(pub while_loop<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            start: while_loop_start >>
-            body: while_loop_body >>
-            (
-                {
-                    Token::WhileLoop{
-                        start: Box::new(start),
-                        body: Box::new(body)
-                    }
+named!(pub while_loop<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        head: while_loop_start >>
+        body: while_loop_body >>
+        end: position >>
+        (
+            {
+                Token::WhileLoop{
+                    start: Box::new(head),
+                    body: Box::new(body),
+                    span: start.span_to(end)
                 }
-            )
+            }
         )
     )
 );
 
 /// Highest level parser that looks for either a for loop or while loop
-named!(pub a_loop<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            l: alt!(
-                while_loop |
-                for_loop
-            ) >> 
-            (
-                {
-                    l
-                }
-            )
+named!(pub a_loop<Lexemes, Token>,
+    do_parse!(
+        l: alt!(
+            while_loop |
+            for_loop
+        ) >> 
+        (
+            {
+                l
+            }
         )
     )
 );
 
 mod tests {
     use super::*;
+    use lexer::lex;
 
     #[test]
     fn test_parse_for_loop_start() {
-        let result = for_loop_start(CompleteStr("for file in files:"));
+        let lexemes = lex("for file in files:").unwrap();
+        let result = for_loop_start(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_parse_while_loop_start() {
-        let result = while_loop_start(CompleteStr("while x < 1:"));
+        let lexemes = lex("while x < 1:").unwrap();
+        let result = while_loop_start(Lexemes::new(&lexemes));
         assert!(result.is_ok());
     }
 }
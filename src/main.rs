@@ -1,21 +1,174 @@
 #![allow(unused)]
 #[macro_use]
 extern crate nom;
+#[macro_use]
+extern crate clap;
 extern crate iridium;
+extern crate rustyline;
+#[cfg(feature = "unicode-identifiers")]
+extern crate unicode_xid;
 
+#[macro_use]
+mod parse_errors;
 mod tokens;
 mod operator_parsers;
 mod expression_parsers;
 mod program_parsers;
+mod statement_parsers;
 mod visitor;
 mod factor_parsers;
-mod term_parsers;
 mod function_parsers;
 mod loop_parsers;
 mod conditional_parsers;
 mod scope;
 mod list_parsers;
 mod dictionary_parsers;
+mod repl;
+mod compiler_errors;
+mod liveness;
+mod indentation;
+mod lexer;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process;
+
+use clap::{App, ArgMatches};
+use iridium::vm::{VM, VMEventType};
+
+use program_parsers::parse_source;
+use repl::Repl;
+use tokens::Token;
+use visitor::{Compiler, Visitor};
+
+/// Exit code for when a file could not be read or written
+const EXIT_IO_ERROR: i32 = 1;
+/// Exit code for when the source could not be parsed
+const EXIT_PARSE_ERROR: i32 = 2;
+/// Exit code for when the parsed program could not be turned into bytecode
+const EXIT_COMPILE_ERROR: i32 = 3;
+/// Exit code for when the VM did not stop at a `HLT`
+const EXIT_RUNTIME_ERROR: i32 = 4;
+
 fn main() {
-    
+    let yaml = load_yaml!("cli.yml");
+    let matches = App::from_yaml(yaml).get_matches();
+
+    match matches.subcommand() {
+        ("compile", Some(args)) => compile(args),
+        ("emit-asm", Some(args)) => emit_asm(args),
+        ("check", Some(args)) => check(args),
+        ("run", Some(args)) => run(args),
+        _ => {
+            let mut repl = Repl::new();
+            repl.run();
+        }
+    }
+}
+
+/// Compiles the input file and writes the bytecode next to it, or to the file given with `--output`
+fn compile(args: &ArgMatches) {
+    let input_file = args.value_of("INPUT_FILE").unwrap();
+    let output_file = match args.value_of("OUTPUT_FILE") {
+        Some(f) => f.to_string(),
+        None => Path::new(input_file).with_extension("ir").to_string_lossy().into_owned(),
+    };
+    let mut compiler = generate(input_file);
+    let bytecode = assemble(input_file, &mut compiler);
+    let result = File::create(&output_file).and_then(|mut fh| fh.write_all(&bytecode));
+    if let Err(e) = result {
+        eprintln!("{}: unable to write bytecode: {}", output_file, e);
+        process::exit(EXIT_IO_ERROR);
+    }
+}
+
+/// Prints the assembly the `Compiler` generates for the input file
+fn emit_asm(args: &ArgMatches) {
+    let input_file = args.value_of("INPUT_FILE").unwrap();
+    let compiler = generate(input_file);
+    compiler.print_asm();
+}
+
+/// Runs every stage of compilation but discards the result. Only the exit code matters.
+fn check(args: &ArgMatches) {
+    let input_file = args.value_of("INPUT_FILE").unwrap();
+    let mut compiler = generate(input_file);
+    assemble(input_file, &mut compiler);
+}
+
+/// Compiles the input file and runs it on an Iridium VM in this process. Prints what `main`
+/// returned, or the whole register file if the program has no `main`.
+fn run(args: &ArgMatches) {
+    let input_file = args.value_of("INPUT_FILE").unwrap();
+    let mut compiler = generate(input_file);
+    let bytecode = assemble(input_file, &mut compiler);
+    let mut vm = VM::new();
+    vm.add_bytes(bytecode);
+    let events = vm.run();
+
+    if let Some(event) = events.last() {
+        let code = event.event.stop_code();
+        match event.event {
+            VMEventType::GracefulStop{ .. } if code == 0 => {},
+            _ => {
+                eprintln!("{}: the VM stopped with code {} before reaching HLT", input_file, code);
+                process::exit(EXIT_RUNTIME_ERROR);
+            }
+        }
+    }
+
+    if compiler.has_function("main") {
+        println!("main returned {}", vm.registers[31]);
+    } else {
+        for (register, value) in vm.registers.iter().enumerate() {
+            println!("${}: {}", register, value);
+        }
+    }
+}
+
+/// Reads and parses the input file, then walks the tree with a new `Compiler`
+fn generate(filename: &str) -> Compiler {
+    let source = read_file(filename);
+    let tree = parse(filename, &source);
+    let mut compiler = Compiler::new();
+    if let Err(e) = compiler.visit_token(&tree) {
+        match e.diagnostic(&source) {
+            Some(diagnostic) => eprintln!("{}:{}", filename, diagnostic),
+            None => eprintln!("{}: {}", filename, e),
+        }
+        process::exit(EXIT_COMPILE_ERROR);
+    }
+    compiler
+}
+
+/// Turns the assembly the `Compiler` generated into bytecode
+fn assemble(filename: &str, compiler: &mut Compiler) -> Vec<u8> {
+    match compiler.compile() {
+        Ok(bytecode) => bytecode,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+    }
+}
+
+fn parse(filename: &str, source: &str) -> Token {
+    match parse_source(source) {
+        Ok(tree) => tree,
+        Err(e) => {
+            eprintln!("{}:{}", filename, e);
+            process::exit(EXIT_PARSE_ERROR);
+        }
+    }
+}
+
+fn read_file(filename: &str) -> String {
+    let mut contents = String::new();
+    let result = File::open(Path::new(filename)).and_then(|mut fh| fh.read_to_string(&mut contents));
+    if let Err(e) = result {
+        eprintln!("{}: unable to read file: {}", filename, e);
+        process::exit(EXIT_IO_ERROR);
+    }
+    contents
 }
@@ -1,96 +1,289 @@
 //! Parsers related to `Operators`
 //! 
-use nom::types::CompleteStr;
+use nom::*;
 
 use tokens::Token;
+use lexer::{Lexemes, keyword, symbol};
 
 /// Looks for any of the operators and returns a Token
 /// for the appropriate one
-named!(pub operator<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            token: alt!(
-                tag!("not") |
-                tag!("or") |
-                tag!("and") |
-                tag!("<=") |
-                tag!(">=") |
-                tag!("==") |
-                tag!("+") |
-                tag!("-") |
-                tag!("*") |
-                tag!("/") |
-                tag!(">") |
-                tag!("<") |
-                tag!("=")
+named!(pub operator<Lexemes, Token>,
+    do_parse!(
+        token: alt!(
+            call!(keyword, "not") |
+            call!(keyword, "or") |
+            call!(keyword, "and") |
+            call!(symbol, "<=") |
+            call!(symbol, ">=") |
+            call!(symbol, "==") |
+            call!(symbol, "!=") |
+            call!(symbol, "**") |
+            call!(symbol, "//") |
+            call!(symbol, "<<") |
+            call!(symbol, ">>") |
+            call!(symbol, "+") |
+            call!(symbol, "-") |
+            call!(symbol, "*") |
+            call!(symbol, "/") |
+            call!(symbol, "%") |
+            call!(symbol, "&") |
+            call!(symbol, "|") |
+            call!(symbol, "^") |
+            call!(symbol, ">") |
+            call!(symbol, "<")
         ) >>
         (
             {
-                match token {
-                    CompleteStr("or") => Token::LogicalOr,
-                    CompleteStr("and") => Token::LogicalAnd,
-                    CompleteStr("+") => Token::AdditionOperator,
-                    CompleteStr("-") => Token::SubtractionOperator,
-                    CompleteStr("*") => Token::MultiplicationOperator,
-                    CompleteStr("/") => Token::DivisionOperator,
-                    CompleteStr(">") => Token::GreaterThan,
-                    CompleteStr("<") => Token::LessThan,
-                    CompleteStr(">=") => Token::GreaterThanOrEqual,
-                    CompleteStr("<=") => Token::LessThanOrEqual,
-                    CompleteStr("==") => Token::EqualTo,
-                    CompleteStr("=") => Token::Assignment,
-                    CompleteStr(&_) => { unreachable!() },
+                match token.text.as_str() {
+                    "not" => Token::LogicalNot,
+                    "or" => Token::LogicalOr,
+                    "and" => Token::LogicalAnd,
+                    "+" => Token::AdditionOperator,
+                    "-" => Token::SubtractionOperator,
+                    "*" => Token::MultiplicationOperator,
+                    "/" => Token::DivisionOperator,
+                    "//" => Token::FloorDivisionOperator,
+                    "%" => Token::ModuloOperator,
+                    "**" => Token::ExponentOperator,
+                    "&" => Token::BitwiseAnd,
+                    "|" => Token::BitwiseOr,
+                    "^" => Token::BitwiseXor,
+                    "<<" => Token::ShiftLeft,
+                    ">>" => Token::ShiftRight,
+                    ">" => Token::GreaterThan,
+                    "<" => Token::LessThan,
+                    ">=" => Token::GreaterThanOrEqual,
+                    "<=" => Token::LessThanOrEqual,
+                    "==" => Token::EqualTo,
+                    "!=" => Token::NotEqualTo,
+                    _ => { unreachable!() },
                 }
             }
         )
-        )
+    )
+);
 
+/// Looks for an operator that goes in front of its operand, such as the `-` of `-x`
+named!(pub prefix_operator<Lexemes, Token>,
+    do_parse!(
+        token: alt!(
+            call!(keyword, "not") |
+            call!(symbol, "+") |
+            call!(symbol, "-") |
+            call!(symbol, "~")
+        ) >>
+        (
+            {
+                match token.text.as_str() {
+                    "not" => Token::LogicalNot,
+                    "+" => Token::AdditionOperator,
+                    "-" => Token::SubtractionOperator,
+                    "~" => Token::BitwiseNot,
+                    _ => { unreachable!() },
+                }
+            }
+        )
     )
 );
 
+/// How tightly an operator holds on to its operands, loosest first. An operator takes
+/// everything around it that binds tighter as its operands, so `1 + 2 * 3` is `1 + (2 * 3)`
+/// and `a or b and c` is `a or (b and c)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    /// Below every operator. An expression is parsed at this precedence.
+    Lowest,
+    /// `or`
+    Or,
+    /// `and`
+    And,
+    /// `not`
+    Not,
+    /// `< > <= >= == !=`
+    Comparison,
+    /// `|`
+    BitwiseOr,
+    /// `^`
+    BitwiseXor,
+    /// `&`
+    BitwiseAnd,
+    /// `<< >>`
+    Shift,
+    /// `+ -`
+    Sum,
+    /// `* / // %`
+    Product,
+    /// `-`, `+` and `~` in front of a single operand
+    Unary,
+    /// `**`. It binds tighter than a `-` on its left, so `-2 ** 2` is `-(2 ** 2)`.
+    Exponent,
+}
+
+impl Precedence {
+    /// Returns the precedence just below this one
+    pub fn below(self) -> Precedence {
+        match self {
+            Precedence::Lowest | Precedence::Or => Precedence::Lowest,
+            Precedence::And => Precedence::Or,
+            Precedence::Not => Precedence::And,
+            Precedence::Comparison => Precedence::Not,
+            Precedence::BitwiseOr => Precedence::Comparison,
+            Precedence::BitwiseXor => Precedence::BitwiseOr,
+            Precedence::BitwiseAnd => Precedence::BitwiseXor,
+            Precedence::Shift => Precedence::BitwiseAnd,
+            Precedence::Sum => Precedence::Shift,
+            Precedence::Product => Precedence::Sum,
+            Precedence::Unary => Precedence::Product,
+            Precedence::Exponent => Precedence::Unary,
+        }
+    }
+}
+
+/// Returns the precedence of `operator` when it stands between two operands. `not` only goes in
+/// front of its operand, so it has none, and neither has `=`, which is only part of an assignment
+/// statement.
+pub fn infix_precedence(operator: &Token) -> Option<Precedence> {
+    match *operator {
+        Token::LogicalOr => Some(Precedence::Or),
+        Token::LogicalAnd => Some(Precedence::And),
+        Token::GreaterThan |
+        Token::LessThan |
+        Token::GreaterThanOrEqual |
+        Token::LessThanOrEqual |
+        Token::EqualTo |
+        Token::NotEqualTo => Some(Precedence::Comparison),
+        Token::BitwiseOr => Some(Precedence::BitwiseOr),
+        Token::BitwiseXor => Some(Precedence::BitwiseXor),
+        Token::BitwiseAnd => Some(Precedence::BitwiseAnd),
+        Token::ShiftLeft | Token::ShiftRight => Some(Precedence::Shift),
+        Token::AdditionOperator | Token::SubtractionOperator => Some(Precedence::Sum),
+        Token::MultiplicationOperator |
+        Token::DivisionOperator |
+        Token::FloorDivisionOperator |
+        Token::ModuloOperator => Some(Precedence::Product),
+        Token::ExponentOperator => Some(Precedence::Exponent),
+        _ => None,
+    }
+}
+
+/// Returns the precedence of `operator` when it stands in front of its operand. Its operand is
+/// everything after it that binds tighter, so `not x == y` is `not (x == y)` and `-x * y` is
+/// `(-x) * y`.
+pub fn prefix_precedence(operator: &Token) -> Option<Precedence> {
+    match *operator {
+        Token::LogicalNot => Some(Precedence::Not),
+        Token::AdditionOperator |
+        Token::SubtractionOperator |
+        Token::BitwiseNot => Some(Precedence::Unary),
+        _ => None,
+    }
+}
+
+/// Checks if a chain of `operator`s groups from the right. `2 ** 3 ** 2` is `2 ** (3 ** 2)`,
+/// while `1 - 2 - 3` is `(1 - 2) - 3`.
+pub fn is_right_associative(operator: &Token) -> bool {
+    matches!(*operator, Token::ExponentOperator)
+}
+
 mod tests {
     use super::*;
     use tokens::Token;
-    use nom::types::CompleteStr;
+    use lexer::lex;
 
     #[test]
     fn test_parse_addition_operator() {
-        let result = operator(CompleteStr("+"));
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("+").unwrap();
+        let result = operator(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::AdditionOperator);
     }
 
     #[test]
     fn test_parse_subtraction_operator() {
-        let result = operator(CompleteStr("-"));
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("-").unwrap();
+        let result = operator(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::SubtractionOperator);
     }
 
     #[test]
     fn test_parse_multiplication_operator() {
-        let result = operator(CompleteStr("*"));
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("*").unwrap();
+        let result = operator(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::MultiplicationOperator);
     }
 
     #[test]
     fn test_parse_division_operator() {
-        let result = operator(CompleteStr("/"));
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex("/").unwrap();
+        let result = operator(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::DivisionOperator);
     }
 
+    #[test]
+    fn test_parse_not_operator() {
+        let lexemes = lex("not").unwrap();
+        let (_, token) = operator(Lexemes::new(&lexemes)).unwrap();
+        assert_eq!(token, Token::LogicalNot);
+    }
+
+    #[test]
+    fn test_parse_prefix_operator() {
+        let operators = [
+            ("not", Token::LogicalNot),
+            ("-", Token::SubtractionOperator),
+            ("+", Token::AdditionOperator),
+            ("~", Token::BitwiseNot),
+        ];
+        for &(source, ref expected) in &operators {
+            let lexemes = lex(source).unwrap();
+            let (_, token) = prefix_operator(Lexemes::new(&lexemes)).unwrap();
+            assert_eq!(token, *expected);
+        }
+        let lexemes = lex("*").unwrap();
+        assert!(prefix_operator(Lexemes::new(&lexemes)).is_err());
+    }
+
+    #[test]
+    fn test_precedence_table() {
+        let table = [
+            Token::LogicalOr,
+            Token::LogicalAnd,
+            Token::EqualTo,
+            Token::BitwiseOr,
+            Token::BitwiseXor,
+            Token::BitwiseAnd,
+            Token::ShiftLeft,
+            Token::AdditionOperator,
+            Token::MultiplicationOperator,
+            Token::ExponentOperator,
+        ];
+        for pair in table.windows(2) {
+            assert!(infix_precedence(&pair[0]) < infix_precedence(&pair[1]));
+        }
+        assert_eq!(infix_precedence(&Token::LogicalNot), None);
+        assert_eq!(infix_precedence(&Token::Assignment), None);
+        assert_eq!(prefix_precedence(&Token::LogicalNot), Some(Precedence::Not));
+        assert_eq!(prefix_precedence(&Token::SubtractionOperator), Some(Precedence::Unary));
+        assert!(Precedence::And < Precedence::Not && Precedence::Not < Precedence::Comparison);
+        assert!(Precedence::Unary < Precedence::Exponent);
+        assert_eq!(Precedence::Exponent.below(), Precedence::Unary);
+        assert_eq!(Precedence::Or.below(), Precedence::Lowest);
+    }
+
     #[test]
     fn test_parse_operator() {
-        let operators = vec!["+", "*", "-", "/", ">", "<", "<=", ">="];
+        let operators = vec!["+", "*", "-", "/", ">", "<", "<=", ">=", "!=", "%", "**", "//", "&", "|", "^", "<<", ">>"];
         for o in operators {
-            let result = operator(CompleteStr(o));
-            assert_eq!(result.is_ok(), true);
+            let lexemes = lex(o).unwrap();
+            let result = operator(Lexemes::new(&lexemes));
+            assert!(result.is_ok());
         }
     }
 }
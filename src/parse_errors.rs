@@ -0,0 +1,187 @@
+//! Contains the errors the parsers can return, and the `expect!` macro that produces them.
+//!
+//! Most parsers backtrack when they fail, so `alt!` can try the next alternative. Once a
+//! parser has seen enough to know what it is looking at, such as the `(` of a parenthesized
+//! expression, a missing piece is an error in the source. `expect!` turns the failure into a
+//! `nom::Err::Failure` tagged with what was `Expected`, which stops the backtracking and ends
+//! up in the verbose error `parse_source` gets back.
+use std::error::Error;
+use std::fmt;
+
+use nom;
+
+use lexer::{Lexemes, LexemeKind};
+
+/// Runs a parser that has to succeed. If it fails, parsing stops with an error that says what
+/// was expected there.
+///
+/// # Example
+///
+/// ```
+/// call!(symbol, "(") >>
+/// value: expression >>
+/// expect!(Expected::ClosingParen, call!(symbol, ")")) >>
+/// ```
+macro_rules! expect (
+    ($i:expr, $expected:expr, $submac:ident!( $($args:tt)* )) => (
+        return_error!($i, ::nom::ErrorKind::Custom($expected as u32), $submac!($($args)*))
+    );
+    ($i:expr, $expected:expr, $f:expr) => (
+        expect!($i, $expected, call!($f))
+    );
+);
+
+/// What a parser was looking for when it failed. It is stored in a `nom::ErrorKind::Custom`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expected {
+    Statement,
+    Expression,
+    Identifier,
+    EndOfLine,
+    Colon,
+    OpeningParen,
+    ClosingParen,
+    ClosingBracket,
+    In,
+    /// A `,` before the next name or value, or the `)` after the last one
+    CommaOrClosingParen,
+}
+
+impl Expected {
+    /// Every `Expected`, in the order of their codes
+    const ALL: [Expected; 10] = [
+        Expected::Statement,
+        Expected::Expression,
+        Expected::Identifier,
+        Expected::EndOfLine,
+        Expected::Colon,
+        Expected::OpeningParen,
+        Expected::ClosingParen,
+        Expected::ClosingBracket,
+        Expected::In,
+        Expected::CommaOrClosingParen,
+    ];
+
+    fn from_code(code: u32) -> Option<Expected> {
+        Expected::ALL.get(code as usize).cloned()
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expected::Statement => write!(f, "a statement"),
+            Expected::Expression => write!(f, "an expression"),
+            Expected::Identifier => write!(f, "a name"),
+            Expected::EndOfLine => write!(f, "the end of the line"),
+            Expected::Colon => write!(f, "`:`"),
+            Expected::OpeningParen => write!(f, "`(`"),
+            Expected::ClosingParen => write!(f, "`)`"),
+            Expected::CommaOrClosingParen => write!(f, "`,` or `)`"),
+            Expected::ClosingBracket => write!(f, "`]`"),
+            Expected::In => write!(f, "`in`"),
+        }
+    }
+}
+
+/// A problem with the source, at a line and column. It is shown with the line it is on and a
+/// caret under the column:
+///
+/// ```
+/// 2:11: expected `)`, found the end of the line
+/// y = (2 + 3
+///           ^
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// 1-based line number in the source
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    pub message: String,
+    /// The line of the source the error is on, without its line break
+    pub source_line: String,
+}
+
+impl ParseError {
+    /// Creates a `ParseError` at `line` and `column` of `source`
+    pub fn new(source: &str, line: usize, column: usize, message: String) -> ParseError {
+        let source_line = source.lines().nth(line - 1).unwrap_or("").to_string();
+        ParseError { line, column, message, source_line }
+    }
+
+    /// Creates a `ParseError` saying `expected` was expected where `input` starts. `input` is
+    /// what was left of the `Lexeme`s of `source`.
+    pub fn expected(source: &str, expected: Expected, input: Lexemes) -> ParseError {
+        let (span, found) = match input.first() {
+            Some(lexeme) => (lexeme.span, describe(lexeme.kind, &lexeme.text)),
+            None => (::indentation::end_of(source.trim_end()), "the end of the input".to_string()),
+        };
+        ParseError::new(source, span.line, span.column, format!("expected {}, found {}", expected, found))
+    }
+
+    /// Turns the verbose error of a failed parse into a `ParseError`. The innermost `Expected`
+    /// in the error wins. Without one, a statement was expected where the error started.
+    pub fn from_context(source: &str, context: nom::Context<Lexemes>) -> ParseError {
+        let errors = match context {
+            nom::Context::Code(input, kind) => vec![(input, kind)],
+            nom::Context::List(errors) => errors,
+        };
+        for &(input, ref kind) in &errors {
+            if let nom::ErrorKind::Custom(code) = *kind {
+                if let Some(expected) = Expected::from_code(code) {
+                    return ParseError::expected(source, expected, input);
+                }
+            }
+        }
+        match errors.first() {
+            Some(&(input, _)) => ParseError::expected(source, Expected::Statement, input),
+            None => ParseError::new(source, 1, 1, "unable to parse input".to_string()),
+        }
+    }
+}
+
+/// Describes a `Lexeme` for the "found" part of an error
+fn describe(kind: LexemeKind, text: &str) -> String {
+    match kind {
+        LexemeKind::Newline => "the end of the line".to_string(),
+        LexemeKind::Indent => "an indented block".to_string(),
+        LexemeKind::Dedent => "the end of the block".to_string(),
+        LexemeKind::Keyword => format!("the keyword `{}`", text),
+        _ => format!("`{}`", text),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Keep the tabs of the line, so the caret lines up however wide a tab is shown
+        let padding: String = self.source_line.chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{}:{}: {}\n{}\n{}^", self.line, self.column, self.message, self.source_line, padding)
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_parse_error() {
+        let error = ParseError::new("x = 1\n\ty = (2\n", 2, 8, "expected `)`, found the end of the line".to_string());
+        assert_eq!(error.to_string(), "2:8: expected `)`, found the end of the line\n\ty = (2\n\t      ^");
+    }
+
+    #[test]
+    fn test_expected_codes() {
+        for expected in Expected::ALL.iter() {
+            assert_eq!(Expected::from_code(*expected as u32), Some(*expected));
+        }
+    }
+}
@@ -1,37 +1,262 @@
 //! Contains the parser for an entire program. This is the
 //! topmost level parser
-use nom::types::CompleteStr;
+use nom::*;
 
-use expression_parsers::*;
+use indentation::{mark_blocks, IndentationErrorKind};
+use lexer::{lex, position, Lexeme, Lexemes};
+use parse_errors::{Expected, ParseError};
+use statement_parsers::statements;
 use tokens::Token;
 
-/// Parses an entire program, which is just a collection of expressions
-named!(pub program<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            expressions: many1!(expression) >>
-            (
-                Token::Program {
-                    expressions: expressions
-                }
-            )
+/// Parses an entire program, which is just a collection of statements. A file that is empty,
+/// or only has comments and blank lines, is a program without any. The lexemes have to go
+/// through `indentation::mark_blocks` first, or blocks will run to the end of the program.
+named!(pub program<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        expressions: alt!(
+            map!(eof!(), |_| vec![]) |
+            statements
+        ) >>
+        end: position >>
+        (
+            Token::Program {
+                expressions,
+                span: start.span_to(end)
+            }
         )
     )
 );
 
+/// Splits `source` into `Lexeme`s and marks its blocks
+pub fn lex_source(source: &str) -> Result<Vec<Lexeme>, ParseError> {
+    let lexemes = lex(source)
+        .map_err(|e| ParseError::new(source, e.line, e.column, e.kind.to_string()))?;
+    mark_blocks(source, lexemes).map_err(|e| {
+        // An indented line usually means the line before it was meant to open a block, such as
+        // an `if` missing its `:`. If the lines before it don't parse, that error comes first.
+        if e.kind == IndentationErrorKind::UnexpectedIndent && e.line > 1 {
+            let line_start = source.match_indices('\n').nth(e.line - 2).map_or(0, |(index, _)| index + 1);
+            if let Err(error) = parse_source(&source[..line_start]) {
+                return error;
+            }
+        }
+        ParseError::new(source, e.line, e.column, e.kind.to_string())
+    })
+}
+
+/// Parses the source of a whole file. The error says where parsing stopped and what was
+/// expected there.
+pub fn parse_source(source: &str) -> Result<Token, ParseError> {
+    let lexemes = lex_source(source)?;
+    match program(Lexemes::new(&lexemes)) {
+        Ok((ref remainder, ref tree)) if remainder.is_empty() => Ok(tree.clone()),
+        Ok((remainder, _)) => Err(ParseError::expected(source, Expected::Statement, remainder)),
+        Err(Err::Error(context)) | Err(Err::Failure(context)) => Err(ParseError::from_context(source, context)),
+        Err(Err::Incomplete(_)) => {
+            let input = Lexemes::new(&lexemes[lexemes.len()..]);
+            Err(ParseError::expected(source, Expected::Statement, input))
+        },
+    }
+}
+
 mod tests {
     use super::*;
+    use lexer::Span;
+
     #[test]
     fn test_parse_program() {
-        let test_program = CompleteStr("1+2");
-        let result = program(test_program);
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex_source("1+2").unwrap();
+        let result = program(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_parse_empty_program() {
+        for source in &["", "\n\n", "# only a comment\n\n    # and another"] {
+            match parse_source(source) {
+                Ok(Token::Program{ expressions, .. }) => assert!(expressions.is_empty(), "{:?}", source),
+                other => panic!("Expected an empty program for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_program_with_statements() {
+        let lexemes = lex_source("x = 1\nwhile x < 3:\n    if x == 2:\n        y = x\n    x = x + 1\n").unwrap();
+        let (remainder, tree) = program(Lexemes::new(&lexemes)).unwrap();
+        assert!(remainder.is_empty());
+        match tree {
+            Token::Program{ expressions, .. } => {
+                assert_eq!(expressions.len(), 2);
+                match expressions[1] {
+                    Token::WhileLoop{ .. } => {},
+                    ref other => panic!("Expected a while loop, got {:?}", other),
+                }
+            },
+            other => panic!("Expected a program, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_program_with_comments() {
+        let source = "# leading\ndef add(a, b):  # after a colon\n    # in a body\n        # oddly indented\n    x = a + b  # trailing\n# dedented\n    return x\n\ny = add(y, y)\n# at the end";
+        let lexemes = lex_source(source).unwrap();
+        let (remainder, tree) = program(Lexemes::new(&lexemes)).unwrap();
+        assert!(remainder.is_empty());
+        match tree {
+            Token::Program{ expressions, .. } => assert_eq!(expressions.len(), 2),
+            other => panic!("Expected a program, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_starting_with_keywords() {
+        let source = "def define(definition, iffy):\n    returned = definition\n    elsewhere = iffy\n    return returned\n\n\
+                      for format in inputs:\n    android = format or notable\n    order = android and Nonesuch\n\
+                      while whiled:\n    elifant = inner\n";
+        match parse_source(source) {
+            Ok(Token::Program{ expressions, .. }) => assert_eq!(expressions.len(), 3),
+            other => panic!("Expected a program, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_with_underscores() {
+        let source = "def _scale(tile_count, maxHP2):\n    return tile_count * maxHP2\n\n\
+                      for tile_count in _tiles:\n    __total__ = _scale(tile_count, tile_count)\n";
+        match parse_source(source) {
+            Ok(Token::Program{ expressions, .. }) => {
+                match expressions[0] {
+                    Token::Function{ ref name, ref parameters, .. } => {
+                        assert_eq!(name.to_string(), "_scale");
+                        assert_eq!(parameters.to_string(), "(tile_count, maxHP2)");
+                    },
+                    ref other => panic!("Expected a function, got {:?}", other),
+                }
+                match expressions[1] {
+                    Token::ForLoop{ ref start, .. } => match **start {
+                        Token::ForLoopStart{ ref variable_name, ref collection_name, .. } => {
+                            assert_eq!((variable_name.as_str(), collection_name.as_str()), ("tile_count", "_tiles"));
+                        },
+                        ref other => panic!("Expected the start of a for loop, got {:?}", other),
+                    },
+                    ref other => panic!("Expected a for loop, got {:?}", other),
+                }
+            },
+            other => panic!("Expected a program, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_must_be_separated() {
+        let cases = vec![
+            ("x = a b\n", (1, 7), "expected the end of the line, found `b`"),
+            ("a b c\n", (1, 3), "expected the end of the line, found `b`"),
+            ("def f(a b):\n    return a\n", (1, 9), "expected `,` or `)`, found `b`"),
+            ("y = f(a b)\n", (1, 9), "expected `,` or `)`, found `b`"),
+            ("y = (a b)\n", (1, 8), "expected `)`, found `b`"),
+            ("y = f(,)\n", (1, 7), "expected `)`, found `,`"),
+            ("def f(,):\n    return 1\n", (1, 7), "expected `)`, found `,`"),
+            ("y = (,)\n", (1, 6), "expected `)`, found `,`"),
+        ];
+        for (source, position, message) in cases {
+            let error = parse_source(source).unwrap_err();
+            assert_eq!((error.line, error.column), position, "{}", source);
+            assert_eq!(error.message, message);
+        }
+    }
+
+    #[test]
+    fn test_keywords_are_not_names() {
+        let cases = vec![
+            ("in = 3\n", (1, 1), "expected a statement, found the keyword `in`"),
+            ("x = and + 1\n", (1, 5), "expected an expression, found the keyword `and`"),
+            ("def if(x):\n    return x\n", (1, 5), "expected a name, found the keyword `if`"),
+            ("for None in xs:\n    y = 1\n", (1, 5), "expected a name, found the keyword `None`"),
+        ];
+        for (source, position, message) in cases {
+            let error = parse_source(source).unwrap_err();
+            assert_eq!((error.line, error.column), position, "{}", source);
+            assert_eq!(error.message, message);
+        }
+    }
+
+    #[test]
+    fn test_blocks_end_at_dedent() {
+        let source = "def f(x):\n    y = x\n    return y\n\nz = f(w)\nif z > 0:\n    z = 0\nz\n";
+        match parse_source(source) {
+            Ok(Token::Program{ expressions, .. }) => assert_eq!(expressions.len(), 4),
+            other => panic!("Expected a program, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_colon_comes_before_indent() {
+        let error = parse_source("if x\n    y = 1\n").unwrap_err();
+        assert_eq!(error.to_string(), "1:5: expected `:`, found the end of the line\nif x\n    ^");
+        let error = parse_source("x = 1\nwhile x < 3\n    x = x + 1\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 12));
+        assert_eq!(error.message, "expected `:`, found the end of the line");
+        for (source, position) in &[("x = 1\n    y = 2\n", (2, 5)), ("  x = 1\n", (1, 3))] {
+            let error = parse_source(source).unwrap_err();
+            assert_eq!((error.line, error.column), *position);
+            assert_eq!(error.message, "unexpected indent");
+        }
+    }
+
+    #[test]
+    fn test_parse_source_reports_indentation() {
+        let error = parse_source("if x:\n    y = 1\n  z = 2\n").unwrap_err();
+        assert_eq!((error.line, error.column), (3, 3));
+        assert_eq!(error.message, "indentation doesn't match any enclosing block");
+    }
+
+    #[test]
+    fn test_parse_source_reports_position() {
+        let error = parse_source("x = 1\ny = (2 + 3\n").unwrap_err();
+        assert_eq!(error.to_string(), "2:11: expected `)`, found the end of the line\ny = (2 + 3\n          ^");
+        let error = parse_source("x = 1\ny = 2 3 ]\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 7));
+        assert_eq!(error.message, "expected the end of the line, found `3`");
+        let error = parse_source("x = 1 @ 2").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 7));
+        assert_eq!(error.message, "unexpected character '@'");
+    }
+
+    #[test]
+    fn test_parse_source_reports_expected() {
+        let cases = vec![
+            ("while x < 3 y = 1\n", (1, 13), "expected `:`, found `y`"),
+            ("for x xs:\n    y = x\n", (1, 7), "expected `in`, found `xs`"),
+            ("def f(x):\n    return\n", (2, 11), "expected an expression, found the end of the line"),
+            ("x = 1 +\n", (1, 8), "expected an expression, found the end of the line"),
+            ("if x > 1:\n    y = [1, 2\nz = 3\n", (3, 1), "expected `]`, found `z`"),
+            ("x = 1\nelse:\n    y = 2\n", (2, 1), "expected a statement, found the keyword `else`"),
+        ];
+        for (source, position, message) in cases {
+            let error = parse_source(source).unwrap_err();
+            assert_eq!((error.line, error.column), position, "{}", source);
+            assert_eq!(error.message, message);
+        }
+    }
+
+    #[test]
+    fn test_program_spans() {
+        let source = "x = 1\nif x > 0:\n    y = x + 1\n";
+        let tree = parse_source(source).unwrap();
+        assert_eq!(tree.span(), Some(Span{ start: 0, end: 29, line: 1, column: 1 }));
+        if let Token::Program{ ref expressions, .. } = tree {
+            let conditional = expressions[1].span().unwrap();
+            assert_eq!((conditional.line, conditional.column), (2, 1));
+            assert_eq!(&source[conditional.start..conditional.end], "if x > 0:\n    y = x + 1");
+        }
     }
 
     #[test]
     fn test_parse_nested_program() {
-        let test_program = CompleteStr("(1+2)*3");
-        let result = program(test_program);
-        assert_eq!(result.is_ok(), true);
+        let lexemes = lex_source("(1+2)*3").unwrap();
+        let result = program(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
     }
 }
@@ -1 +1,242 @@
-//! Contains the REPL module for Palladium
\ No newline at end of file
+//! Contains the interactive REPL for Palladium. Every line is compiled with the same
+//! `Compiler`, so variables bound in one line are still in scope in the next. After each
+//! line, the whole session is assembled, and the code of that line is run on an embedded
+//! Iridium VM that keeps its registers and stack from one line to the next.
+use iridium::vm::VM;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use program_parsers::parse_source;
+use tokens::Token;
+use visitor::{Compiler, Visitor};
+
+/// Lines starting with this are commands for the REPL itself and not Palladium code
+const COMMAND_PREFIX: char = '.';
+
+pub static BANNER: &str = "Welcome to Palladium! Type .help for a list of commands.";
+pub static PROMPT: &str = ">>> ";
+pub static CONTINUATION_PROMPT: &str = "... ";
+
+/// Core structure for the Palladium REPL
+pub struct Repl {
+    /// Every line the user has entered, including commands
+    command_buffer: Vec<String>,
+    /// The `Compiler` holding the state of the session
+    compiler: Compiler,
+    /// The tree parsed from the last input, so it can be shown with `.ast`
+    last_tree: Option<Token>,
+    /// The VM every input runs on. The values of the session's variables are in its registers.
+    vm: VM,
+}
+
+impl Repl {
+    /// Creates and returns a new REPL
+    pub fn new() -> Repl {
+        let mut compiler = Compiler::new();
+        compiler.write_prologue();
+        Repl {
+            command_buffer: vec![],
+            compiler,
+            last_tree: None,
+            vm: VM::new(),
+        }
+    }
+
+    /// Reads input from the user until they quit or stdin is closed
+    pub fn run(&mut self) {
+        println!("{}", BANNER);
+        let mut editor = DefaultEditor::new().expect("Unable to read lines from the user");
+        while let Some(buffer) = Repl::read_input(&mut editor) {
+            if buffer.trim().is_empty() {
+                continue;
+            }
+            self.command_buffer.push(buffer.clone());
+
+            if buffer.trim_start().starts_with(COMMAND_PREFIX) {
+                if !self.execute_command(buffer.trim()) {
+                    break;
+                }
+                continue;
+            }
+
+            match self.eval(&buffer) {
+                Ok(Some(value)) => println!("{}", value),
+                Ok(None) => {},
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+
+    /// Compiles `source` into the session and runs it. Only the code of `source` runs, so what
+    /// earlier inputs did isn't done again. Returns the value of the last statement, if it had
+    /// one.
+    pub fn eval(&mut self, source: &str) -> Result<Option<i32>, String> {
+        let tree = parse_source(source).map_err(|e| e.to_string())?;
+
+        // If anything in this input fails to compile, none of it should stay in the session
+        let snapshot = self.compiler.snapshot();
+        self.compiler.mark_entry();
+        if let Token::Program{ ref expressions, .. } = tree {
+            for expression in expressions {
+                if let Err(e) = self.compiler.visit_statement(expression) {
+                    self.compiler.restore(snapshot);
+                    return Err(e.diagnostic(source).map_or_else(|| e.to_string(), |diagnostic| diagnostic.to_string()));
+                }
+            }
+        }
+        self.last_tree = Some(tree);
+
+        let bytecode = match self.compiler.compile() {
+            Ok(bytecode) => bytecode,
+            Err(e) => {
+                self.compiler.restore(snapshot);
+                return Err(e.to_string());
+            }
+        };
+        // `run` starts from the header again, but leaves the registers and stack alone
+        self.vm.program = bytecode;
+        self.vm.run();
+        Ok(self.compiler.result_register().map(|register| self.vm.registers[register as usize]))
+    }
+
+    /// Reads one input with `editor`, which lets the user edit the line and go back through the
+    /// lines entered before. A line ending in `:` starts a block, such as a `def`, which
+    /// continues until the user enters an empty line. Ctrl-C throws away the input typed so far.
+    /// Returns `None` once stdin is closed.
+    fn read_input(editor: &mut DefaultEditor) -> Option<String> {
+        let mut buffer = String::new();
+        let mut prompt = PROMPT;
+        loop {
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    buffer.clear();
+                    prompt = PROMPT;
+                    continue;
+                },
+                Err(_) if buffer.is_empty() => return None,
+                Err(_) => return Some(buffer),
+            };
+            if !line.trim().is_empty() {
+                // Only the history is lost if this fails
+                let _ = editor.add_history_entry(line.as_str());
+            }
+            let in_block = prompt == CONTINUATION_PROMPT;
+            if in_block && line.trim().is_empty() {
+                return Some(buffer);
+            }
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if !in_block && !line.trim_end().ends_with(':') {
+                return Some(buffer);
+            }
+            prompt = CONTINUATION_PROMPT;
+        }
+    }
+
+    /// Runs a REPL command. Returns false if the REPL should exit.
+    fn execute_command(&mut self, input: &str) -> bool {
+        match input {
+            ".quit" | ".exit" => {
+                return false;
+            },
+            ".asm" => {
+                self.compiler.print_asm();
+            },
+            ".ast" => {
+                match self.last_tree {
+                    Some(ref tree) => println!("{:#?}", tree),
+                    None => println!("Nothing has been parsed yet"),
+                }
+            },
+            ".registers" => {
+                self.compiler.print_used_registers();
+                self.compiler.print_free_registers();
+            },
+            ".history" => {
+                for command in &self.command_buffer {
+                    print!("{}", command);
+                }
+            },
+            ".help" => {
+                println!("Each input runs once, on a VM that keeps the values of the inputs before it.");
+                println!("The arrow keys edit the line and go through the history. Ctrl-C clears the input.");
+                println!("Commands available:");
+                println!(".asm - Prints the assembly generated so far");
+                println!(".ast - Prints the tree parsed from the last input");
+                println!(".registers - Prints the registers the compiler is using and has free");
+                println!(".history - Prints everything entered so far");
+                println!(".quit - Exits the REPL");
+            },
+            _ => {
+                println!("Invalid command: {}", input);
+            }
+        };
+        true
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_expression() {
+        let mut repl = Repl::new();
+        let result = repl.eval("1+2");
+        assert_eq!(result, Ok(Some(3)));
+    }
+
+    #[test]
+    fn test_variables_persist_between_inputs() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("x = 5"), Ok(None));
+        assert_eq!(repl.eval("y = x * 2"), Ok(None));
+        assert_eq!(repl.eval("y - x"), Ok(Some(5)));
+    }
+
+    #[test]
+    fn test_reassign_variable() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("x = 5"), Ok(None));
+        assert_eq!(repl.eval("x = x + 1"), Ok(None));
+        assert_eq!(repl.eval("x"), Ok(Some(6)));
+    }
+
+    #[test]
+    fn test_parse_error_keeps_session() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("x = 5"), Ok(None));
+        assert!(repl.eval("x = )").is_err());
+        assert_eq!(repl.eval("x"), Ok(Some(5)));
+    }
+
+    #[test]
+    fn test_earlier_inputs_are_not_run_again() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("x = 5"), Ok(None));
+        // Only running `x = 5` again could undo this
+        let register = repl.compiler.get_variable("x").unwrap();
+        repl.vm.registers[register as usize] = 7;
+        assert_eq!(repl.eval("x + 1"), Ok(Some(8)));
+        assert_eq!(repl.eval("def double(n):\n    return n * 2\n"), Ok(None));
+        assert_eq!(repl.eval("double(x)"), Ok(Some(14)));
+    }
+
+    #[test]
+    fn test_codegen_error_points_at_source() {
+        let mut repl = Repl::new();
+        let error = repl.eval("x = 1\ny = x + z").unwrap_err();
+        assert!(error.starts_with("2:9: Variable z is used before it is assigned"), "{}", error);
+        assert!(error.contains("y = x + z"), "{}", error);
+        assert!(error.contains('^'), "{}", error);
+    }
+
+    #[test]
+    fn test_codegen_error_is_undone() {
+        let mut repl = Repl::new();
+        assert!(repl.eval("y = x").is_err());
+        assert!(repl.eval("y").is_err());
+        assert_eq!(repl.eval("y = 2"), Ok(None));
+        assert_eq!(repl.eval("y"), Ok(Some(2)));
+    }
+}
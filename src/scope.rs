@@ -3,11 +3,19 @@
 
 use std::collections::HashMap;
 
+/// Number of bytes a value takes up on the Iridium stack
+pub const SLOT_SIZE: i32 = 4;
+
+#[derive(Clone)]
 pub struct Scope {
     pub variables: HashMap<String, u8>,
+    /// Variables that were spilled to the Iridium stack, and the `bp_offset` of their slot in
+    /// the `Frame`
+    pub slots: HashMap<String, i32>,
+    /// Variables holding a list, and the registers of the list's elements
+    pub lists: HashMap<String, Vec<u8>>,
     return_registers: Vec<u8>,
     pub used_registers: Vec<u8>,
-    bp_offset: i32,
 }
 
 impl Scope {
@@ -15,9 +23,10 @@ impl Scope {
     pub fn new() -> Scope {
         Scope {
             variables: HashMap::new(),
+            slots: HashMap::new(),
+            lists: HashMap::new(),
             return_registers: vec![],
             used_registers: vec![],
-            bp_offset: 0,
         }
     }
 
@@ -30,6 +39,49 @@ impl Scope {
         self.variables.insert(identifier.to_owned(), register);
     }
 
+    /// Removes a variable, returning the register it lived in
+    pub fn remove_variable(&mut self, variable: &str) -> Option<u8> {
+        self.variables.remove(variable)
+    }
+
+    /// Moves a variable out of its register and into the slot at `offset`, returning the register
+    pub fn spill_variable(&mut self, variable: &str, offset: i32) -> Option<u8> {
+        let register = self.variables.remove(variable)?;
+        self.slots.insert(variable.to_owned(), offset);
+        Some(register)
+    }
+
+    /// Moves a spilled variable back into `register`, returning the offset of the slot it was in
+    pub fn reload_variable(&mut self, variable: &str, register: u8) -> Option<i32> {
+        let offset = self.slots.remove(variable)?;
+        self.variables.insert(variable.to_owned(), register);
+        Some(offset)
+    }
+
+    /// Gets the offset of the slot a spilled variable is in
+    pub fn get_slot(&self, variable: &str) -> Option<i32> {
+        self.slots.get(variable).cloned()
+    }
+
+    /// Removes a spilled variable, returning the offset of its slot
+    pub fn remove_slot(&mut self, variable: &str) -> Option<i32> {
+        self.slots.remove(variable)
+    }
+
+    pub fn new_list(&mut self, identifier: &str, registers: Vec<u8>) {
+        self.lists.insert(identifier.to_owned(), registers);
+    }
+
+    /// Gets the registers of the elements of a list variable
+    pub fn get_list(&self, variable: &str) -> Option<Vec<u8>> {
+        self.lists.get(variable).cloned()
+    }
+
+    /// Removes a list variable, returning the registers of its elements
+    pub fn remove_list(&mut self, variable: &str) -> Option<Vec<u8>> {
+        self.lists.remove(variable)
+    }
+
     pub fn get_variable(&self, variable: &str) -> Option<u8> {
         if let Some(register) = self.variables.get(variable) {
             return Some(register.to_owned());
@@ -43,6 +95,9 @@ impl Scope {
         for register in self.variables.values() {
             variables.push(register.to_owned());
         }
+        for registers in self.lists.values() {
+            variables.extend(registers);
+        }
         variables
     }
 
@@ -57,4 +112,62 @@ impl Scope {
     pub fn clone_all_return_registers(&mut self) -> Vec<u8> {
         self.return_registers.clone()
     }
-}
\ No newline at end of file
+}
+
+/// What a slot of a `Frame` holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum Slot {
+    /// An operand that was spilled in the middle of an expression
+    Operand,
+    /// A variable that was spilled because every register was in use
+    Variable(String),
+    /// A variable that is no longer needed. The slot is popped once everything above it is.
+    Dead,
+}
+
+/// The values a function has spilled onto the Iridium stack, oldest first. Iridium can only
+/// reach the top of its stack, so a slot is popped by popping every slot above it first.
+/// A slot is known by its `bp_offset`: how many bytes the frame held once it was pushed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    pub slots: Vec<Slot>,
+    /// Index of the function's first Scope. Only the variables of that Scope and the ones
+    /// inside it can be spilled into this Frame.
+    pub first_scope: usize,
+}
+
+impl Frame {
+    /// Creates a Frame for a function whose first Scope is at `first_scope`
+    pub fn new(first_scope: usize) -> Frame {
+        Frame {
+            slots: vec![],
+            first_scope,
+        }
+    }
+
+    /// Records that a value was pushed onto the Iridium stack. Returns the offset of its slot.
+    pub fn push(&mut self, slot: Slot) -> i32 {
+        self.slots.push(slot);
+        self.bp_offset()
+    }
+
+    /// Records that the newest slot was popped off the Iridium stack
+    pub fn pop(&mut self) -> Option<Slot> {
+        self.slots.pop()
+    }
+
+    /// How many bytes this Frame currently has on the Iridium stack
+    pub fn bp_offset(&self) -> i32 {
+        self.slots.len() as i32 * SLOT_SIZE
+    }
+
+    /// The index in `slots` of the slot at `offset`
+    pub fn index(offset: i32) -> usize {
+        (offset / SLOT_SIZE - 1) as usize
+    }
+
+    /// The offset of the slot at `index` of `slots`
+    pub fn offset(index: usize) -> i32 {
+        (index as i32 + 1) * SLOT_SIZE
+    }
+}
@@ -0,0 +1,216 @@
+//! Contains the parsers for statements and blocks. A statement is anything that can stand on
+//! its own in a program or in the body of a function, conditional or loop. A block is the
+//! indented statements after a line ending in `:`, between the `Indent` and `Dedent` lexemes
+//! added by `indentation::mark_blocks`.
+//!
+//! ```
+//! x = 1
+//! if x > 0:
+//!     x = x + 1
+//! while x < 10:
+//!     x = x * 2
+//! ```
+use nom::*;
+
+use tokens::Token;
+use conditional_parsers::conditional;
+use loop_parsers::a_loop;
+use function_parsers::{function, return_statement};
+use expression_parsers::expression;
+use lexer::{Lexemes, LexemeKind, lexeme, position, symbol};
+use parse_errors::Expected;
+
+/// Parses a conditional, a loop, a function definition, or a simple statement
+named!(pub statement<Lexemes, Token>,
+    alt!(
+        conditional |
+        a_loop |
+        function |
+        simple_statement
+    )
+);
+
+/// Parses a return statement, an assignment or an expression, which has to be the last thing on
+/// its line. A compound statement, such as a loop, ends at the `Dedent` of its block instead.
+named!(pub simple_statement<Lexemes, Token>,
+    terminated!(
+        alt!(
+            return_statement |
+            assignment |
+            expression
+        ),
+        expect!(Expected::EndOfLine, newline)
+    )
+);
+
+/// Parses an assignment. It is a statement rather than an expression, so it has no value:
+/// `x = y = 1` and `(x = 1) + 1` are errors. The compiler checks that the target is a name.
+///
+/// # Example
+///
+/// ```
+/// x = 1 + 2
+/// ```
+named!(pub assignment<Lexemes, Token>,
+    do_parse!(
+        start: position >>
+        target: expression >>
+        call!(symbol, "=") >>
+        value: expect!(Expected::Expression, expression) >>
+        end: position >>
+        (
+            Token::BinaryOperation{
+                operator: Box::new(Token::Assignment),
+                left: Box::new(target),
+                right: Box::new(value),
+                span: start.span_to(end),
+            }
+        )
+    )
+);
+
+/// Parses statements until one can't be parsed. There has to be at least one. This isn't
+/// `many1!`, which would replace the error of the first statement with its own.
+named!(pub statements<Lexemes, Vec<Token>>,
+    do_parse!(
+        first: statement >>
+        rest: many0!(statement) >>
+        (
+            {
+                let mut statements = vec![first];
+                statements.extend(rest);
+                statements
+            }
+        )
+    )
+);
+
+/// Parses the body of a function, conditional or loop, which comes after the `:`. Usually it
+/// is an indented block. A body can also be a simple statement on the same line as the `:`.
+///
+/// # Example
+///
+/// ```
+/// if x > 1:
+///     y = 2
+/// if x > 1: y = 2
+/// ```
+named!(pub body<Lexemes, Vec<Token>>,
+    expect!(Expected::Statement,
+        alt!(
+            block |
+            map!(simple_statement, |statement| vec![statement])
+        )
+    )
+);
+
+/// Parses an indented block: the end of the line with the `:`, then statements up to the end
+/// of the block
+named!(pub block<Lexemes, Vec<Token>>,
+    do_parse!(
+        newline >>
+        call!(lexeme, LexemeKind::Indent) >>
+        statements: expect!(Expected::Statement, statements) >>
+        expect!(Expected::Statement, call!(lexeme, LexemeKind::Dedent)) >>
+        (
+            statements
+        )
+    )
+);
+
+named!(pub newline<Lexemes, LexemeKind>,
+    map!(call!(lexeme, LexemeKind::Newline), |newline| newline.kind)
+);
+
+mod tests {
+    use super::*;
+    use program_parsers::lex_source;
+
+    #[test]
+    fn test_parse_expression_statement() {
+        let lexemes = lex_source("x = 1+2").unwrap();
+        let result = statement(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_assignment_is_a_statement() {
+        let lexemes = lex_source("x = 1 + 2").unwrap();
+        match statement(Lexemes::new(&lexemes)) {
+            Ok((_, Token::BinaryOperation{ ref operator, .. })) if **operator == Token::Assignment => {},
+            other => panic!("Expected an assignment, got {:?}", other),
+        }
+        for source in &["x = y = 1 + 2", "(x = 4) + 1", "y = (x = 4)", "x = "] {
+            let lexemes = lex_source(source).unwrap();
+            match statement(Lexemes::new(&lexemes)) {
+                Err(Err::Failure(_)) => {},
+                other => panic!("Expected {} to fail, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_conditional_statement() {
+        let lexemes = lex_source("if x > 1:\n    y = 2\nelif x > 0:\n    y = 1\nelse:\n    y = 0").unwrap();
+        let result = statement(Lexemes::new(&lexemes));
+        let (remainder, tree) = result.unwrap();
+        assert!(remainder.is_empty());
+        match tree {
+            Token::Conditional{ branches, .. } => assert_eq!(branches.len(), 3),
+            other => panic!("Expected a conditional, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_loop_statements() {
+        let lexemes = lex_source("while x < 3:\n    x = x + 1").unwrap();
+        let result = statement(Lexemes::new(&lexemes));
+        match result {
+            Ok((_, Token::WhileLoop{ .. })) => {},
+            other => panic!("Expected a while loop, got {:?}", other),
+        }
+        let lexemes = lex_source("for x in xs:\n    y = x").unwrap();
+        let result = statement(Lexemes::new(&lexemes));
+        match result {
+            Ok((_, Token::ForLoop{ .. })) => {},
+            other => panic!("Expected a for loop, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_block() {
+        let lexemes = lex_source("if x:\n    x = 1\n    y = 2\n").unwrap();
+        // Skip `if x:`
+        let result = block(Lexemes::new(&lexemes[3..]));
+        let (remainder, statements) = result.unwrap();
+        assert!(remainder.is_empty());
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn test_block_ends_at_dedent() {
+        let lexemes = lex_source("while x < 3:\n    x = x + 1\ny = x\n").unwrap();
+        let result = statements(Lexemes::new(&lexemes));
+        let (remainder, statements) = result.unwrap();
+        assert!(remainder.is_empty());
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_inline_body() {
+        let lexemes = lex_source("if x > 1: y = 2\nz = 3\n").unwrap();
+        let result = statements(Lexemes::new(&lexemes));
+        let (remainder, statements) = result.unwrap();
+        assert!(remainder.is_empty());
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_nested_statements() {
+        let lexemes = lex_source("while x < 3:\n    if x > 1:\n        y = 2\n    x = x + 1").unwrap();
+        let result = statement(Lexemes::new(&lexemes));
+        assert!(result.is_ok());
+        let (remainder, _) = result.unwrap();
+        assert!(remainder.is_empty());
+    }
+}
@@ -1,5 +1,10 @@
 use std::fmt;
 
+use lexer::Span;
+
+/// A node of the tree the parsers build. Every node with fields carries the `Span` of the
+/// source it was parsed from. Operators don't; they sit between their operands in a
+/// `BinaryOperation`, which does.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Eof,
@@ -7,71 +12,180 @@ pub enum Token {
     SubtractionOperator,
     MultiplicationOperator,
     DivisionOperator,
+    FloorDivisionOperator,
+    ModuloOperator,
+    ExponentOperator,
     GreaterThan,
     LessThan,
     GreaterThanOrEqual,
     LessThanOrEqual,
     EqualTo,
+    NotEqualTo,
     LogicalOr,
     LogicalAnd,
     LogicalNot,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseNot,
+    ShiftLeft,
+    ShiftRight,
     Assignment,
-    Integer { value: i64 },
-    Float { value: f64},
-    Identifier { values: Vec<String> },
-    Factor { value: Box<Token> },
-    Term { left: Box<Token>, right: Vec<(Token, Token)> },
-    If { expr: Box<Token>, body: Vec<Token> },
-    Elif { expr: Box<Token>, body: Vec<Token> },
-    Else { body: Vec<Token> },
-    List { elements: Vec<Token> },
-    ListInterior{ body: String },
-    Dictionary{ keys: Vec<Token>, values: Vec<Token> },
-    DictionaryKeyValuePair{ key: Box<Token>, value: Box<Token> },
-    FunctionCall { name: String, parameters: Box<Token> },
-    FunctionName { name: String },
-    FunctionArgs { args: Vec<String> },
-    FunctionBody { expressions: Vec<Token> },
-    Function { name: Box<Token>, args: Box<Token>, body: Box<Token>, return_statement: Box<Token> },
-    ReturnArgs { args: Vec<Token> },
-    ReturnStatement { parameters: Box<Token> },
-    WhileLoop { start: Box<Token>, body: Box<Token> },
-    WhileLoopStart { expression: Box<Token> },
-    WhileLoopBody { expressions: Vec<Token> },
-    ForLoop { start: Box<Token>, body: Box<Token> },
-    ForLoopStart { variable_name: String, collection_name: String},
-    ForLoopBody { expressions: Vec<Token> },
-    Expression { left: Box<Token>, right: Vec<(Token, Token)> },
-    Program { expressions: Vec<Token> },
+    Integer { value: i64, span: Span },
+    Float { value: f64, span: Span },
+    String { value: String, span: Span },
+    Boolean { value: bool, span: Span },
+    None { span: Span },
+    Identifier { name: String, span: Span },
+    Factor { value: Box<Token>, span: Span },
+    If { expr: Box<Token>, body: Vec<Token>, span: Span },
+    Elif { expr: Box<Token>, body: Vec<Token>, span: Span },
+    Else { body: Vec<Token>, span: Span },
+    Conditional { branches: Vec<Token>, span: Span },
+    List { elements: Vec<Token>, span: Span },
+    Tuple { elements: Vec<Token>, span: Span },
+    ListInterior { body: String, span: Span },
+    Dictionary { keys: Vec<Token>, values: Vec<Token>, span: Span },
+    DictionaryKeyValuePair { key: Box<Token>, value: Box<Token>, span: Span },
+    FunctionCall { name: String, arguments: Box<Token>, span: Span },
+    /// The values passed to a function, between the parentheses of a `FunctionCall`
+    Arguments { values: Vec<Token>, span: Span },
+    FunctionName { name: String, span: Span },
+    /// The names a function gives its arguments, between the parentheses of its definition
+    Parameters { names: Vec<String>, span: Span },
+    FunctionBody { expressions: Vec<Token>, span: Span },
+    Function { name: Box<Token>, parameters: Box<Token>, body: Box<Token>, docstring: Option<String>, span: Span },
+    ReturnStatement { parameters: Box<Token>, span: Span },
+    WhileLoop { start: Box<Token>, body: Box<Token>, span: Span },
+    WhileLoopStart { expression: Box<Token>, span: Span },
+    WhileLoopBody { expressions: Vec<Token>, span: Span },
+    ForLoop { start: Box<Token>, body: Box<Token>, span: Span },
+    ForLoopStart { variable_name: String, collection_name: String, span: Span },
+    ForLoopBody { expressions: Vec<Token>, span: Span },
+    BinaryOperation { operator: Box<Token>, left: Box<Token>, right: Box<Token>, span: Span },
+    UnaryOperation { operator: Box<Token>, operand: Box<Token>, span: Span },
+    Program { expressions: Vec<Token>, span: Span },
+}
+
+impl Token {
+    /// Returns the `Span` of the source this node was parsed from. Operators and `Eof` don't
+    /// have one.
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Token::Integer{ span, .. } |
+            Token::Float{ span, .. } |
+            Token::String{ span, .. } |
+            Token::Boolean{ span, .. } |
+            Token::None{ span } |
+            Token::Identifier{ span, .. } |
+            Token::Factor{ span, .. } |
+            Token::If{ span, .. } |
+            Token::Elif{ span, .. } |
+            Token::Else{ span, .. } |
+            Token::Conditional{ span, .. } |
+            Token::List{ span, .. } |
+            Token::Tuple{ span, .. } |
+            Token::ListInterior{ span, .. } |
+            Token::Dictionary{ span, .. } |
+            Token::DictionaryKeyValuePair{ span, .. } |
+            Token::FunctionCall{ span, .. } |
+            Token::Arguments{ span, .. } |
+            Token::FunctionName{ span, .. } |
+            Token::Parameters{ span, .. } |
+            Token::FunctionBody{ span, .. } |
+            Token::Function{ span, .. } |
+            Token::ReturnStatement{ span, .. } |
+            Token::WhileLoop{ span, .. } |
+            Token::WhileLoopStart{ span, .. } |
+            Token::WhileLoopBody{ span, .. } |
+            Token::ForLoop{ span, .. } |
+            Token::ForLoopStart{ span, .. } |
+            Token::ForLoopBody{ span, .. } |
+            Token::BinaryOperation{ span, .. } |
+            Token::UnaryOperation{ span, .. } |
+            Token::Program{ span, .. } => Some(span),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Token::FunctionName{ name } => {
+            Token::FunctionName{ name, .. } => {
                 write!(f, "{}", name)
             },
             Token::Assignment => {
                 write!(f, "Assignment")
             },
-            Token::Identifier{ values } => {
-                write!(f, "{:#?}", values)
+            Token::AdditionOperator => write!(f, "+"),
+            Token::SubtractionOperator => write!(f, "-"),
+            Token::MultiplicationOperator => write!(f, "*"),
+            Token::DivisionOperator => write!(f, "/"),
+            Token::FloorDivisionOperator => write!(f, "//"),
+            Token::ModuloOperator => write!(f, "%"),
+            Token::ExponentOperator => write!(f, "**"),
+            Token::GreaterThan => write!(f, ">"),
+            Token::LessThan => write!(f, "<"),
+            Token::GreaterThanOrEqual => write!(f, ">="),
+            Token::LessThanOrEqual => write!(f, "<="),
+            Token::EqualTo => write!(f, "=="),
+            Token::NotEqualTo => write!(f, "!="),
+            Token::LogicalOr => write!(f, "or"),
+            Token::LogicalAnd => write!(f, "and"),
+            Token::LogicalNot => write!(f, "not"),
+            Token::BitwiseAnd => write!(f, "&"),
+            Token::BitwiseOr => write!(f, "|"),
+            Token::BitwiseXor => write!(f, "^"),
+            Token::BitwiseNot => write!(f, "~"),
+            Token::ShiftLeft => write!(f, "<<"),
+            Token::ShiftRight => write!(f, ">>"),
+            Token::Float{ value, .. } => {
+                write!(f, "{}", value)
+            },
+            Token::String{ value, .. } => {
+                write!(f, "{:?}", value)
             },
-            Token::Integer{ value } => {
+            Token::Boolean{ value: true, .. } => write!(f, "True"),
+            Token::Boolean{ value: false, .. } => write!(f, "False"),
+            Token::None{ .. } => write!(f, "None"),
+            Token::FunctionCall{ ref name, ref arguments, .. } => {
+                write!(f, "{}{}", name, arguments)
+            },
+            Token::Arguments{ values: elements, .. } | Token::Tuple{ elements, .. } => {
+                let elements: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+                match elements.len() {
+                    1 if matches!(self, Token::Tuple{ .. }) => write!(f, "({},)", elements[0]),
+                    _ => write!(f, "({})", elements.join(", ")),
+                }
+            },
+            Token::Parameters{ names, .. } => {
+                write!(f, "({})", names.join(", "))
+            },
+            Token::Identifier{ name, .. } => {
+                write!(f, "{}", name)
+            },
+            Token::Integer{ value, .. } => {
                 write!(f, "{}", value)
             },
-            Token::Expression{ left, right } => {
-                write!(f, "Expression")
+            Token::BinaryOperation{ operator, left, right, .. } => {
+                write!(f, "{} {} {}", left, operator, right)
             },
-            Token::Program{ expressions } => {
-                write!(f, "Program")
+            Token::UnaryOperation{ operator, operand, .. } => {
+                match **operator {
+                    Token::LogicalNot => write!(f, "not {}", operand),
+                    _ => write!(f, "{}{}", operator, operand),
+                }
             },
-            Token::Term{ left, right } => {
-                write!(f, "Term")
+            Token::Program{ expressions, .. } => {
+                write!(f, "Program")
             },
-            Token::Factor{ value } => {
+            Token::Factor{ value, .. } => {
                 write!(f, "{}", value)
             },
+            Token::If{ .. } => write!(f, "if"),
+            Token::Elif{ .. } => write!(f, "elif"),
+            Token::Else{ .. } => write!(f, "else"),
             _ => {
                 write!(f, "unknown")
             }
@@ -1,13 +1,29 @@
 //! Contains the `Compiler` and `Visitor` trait. These are used to compile
 //! a Palladium program to assembler code for the `Iridium` VM.
 use std::collections::HashMap;
-use iridium::assembler::Assembler;
+use std::fmt;
+use std::mem;
+use nom::types::CompleteStr;
+use iridium::assembler::{Assembler, PIE_HEADER_LENGTH};
+use iridium::assembler::Token as AssemblerToken;
+use iridium::assembler::assembler_errors::AssemblerError;
+use iridium::assembler::instruction_parsers::AssemblerInstruction;
+use iridium::assembler::program_parsers::program as assembly_program;
+use iridium::instruction::Opcode;
 use tokens::Token;
-use scope::Scope;
+use scope::{Frame, Scope, Slot};
+use liveness;
+use compiler_errors::{AssemblyLine, CodegenError, CompileError};
+
+/// Register that always holds 0. It is never allocated, so it stays 0 for the whole program.
+const ZERO_REGISTER: u8 = 30;
+/// Register a function leaves its return value in. It is never allocated either, so code that
+/// needs a register for a moment, such as a value being thrown away, can use it between calls.
+const RETURN_REGISTER: u8 = 31;
 
 pub trait Visitor {
     /// This function is called for ever Token in the AST
-    fn visit_token(&mut self, node: &Token);
+    fn visit_token(&mut self, node: &Token) -> Result<(), CodegenError>;
 }
 
 #[derive(Default)]
@@ -16,8 +32,8 @@ pub trait Visitor {
 pub struct Compiler {
     /// Unused Registers
     free_registers: Vec<u8>,
-    /// Used Registers
-    used_registers: Vec<u8>,
+    /// Operand stack. Holds where each value computed but not used yet lives.
+    used_registers: Vec<Operand>,
     /// The assembly statements created so far. These are just Strings that are
     /// emitted by the `Compiler` as it walks the tree
     assembly: Vec<String>,
@@ -26,53 +42,227 @@ pub struct Compiler {
     assembler: Assembler,
     scopes: Vec<Scope>,
     scope_pointer: usize,
-    identifier_buffer: Vec<String>,
-    in_return: bool
+    /// Register holding the value of the most recently visited statement
+    result_register: Option<u8>,
+    /// Names of the functions defined so far, and how many parameters each takes
+    functions: Vec<(String, usize)>,
+    /// Used to make every generated label unique
+    label_counter: usize,
+    /// How many function definitions the `Compiler` is inside of
+    function_depth: usize,
+    /// The distinct string literals seen so far. See `data_lines` for where they end up.
+    strings: Vec<String>,
+    /// The name of every function called so far, how many arguments it was given, and the
+    /// call. A function can be called before it is defined, so `Token::Program` checks them
+    /// once everything is visited.
+    calls: Vec<(String, usize, Token)>,
+    /// The slots the function being visited has on the Iridium stack
+    frame: Frame,
+    /// The variables the statement being visited mentions. Their registers may be operands of
+    /// what is being compiled, so they are never spilled.
+    mentions: Vec<String>,
+    /// For every list of statements being visited, innermost last, the variables each of the
+    /// statements mentions and which statement is being visited. Used to spill the variable
+    /// that is needed last.
+    upcoming: Vec<(Vec<Vec<String>>, usize)>,
+    /// The label the program starts at, if it doesn't start at the top of `.code`. See
+    /// `mark_entry`.
+    entry: Option<String>,
 }
 
 impl Compiler {
     /// Creates and returns a new `Compiler`
     pub fn new() -> Compiler {
         let mut free_registers = vec![];
-        for i in 0..31 {
+        for i in 0..ZERO_REGISTER {
             free_registers.push(i);
         }
         free_registers.reverse();
         Compiler{
-            free_registers: free_registers,
+            free_registers,
             used_registers: vec![],
             assembly: vec![],
             assembler: Assembler::new(),
             scopes: vec![Scope::new()],
             scope_pointer: 1,
-            identifier_buffer: vec![],
-            in_return: false
+            result_register: None,
+            functions: vec![],
+            label_counter: 0,
+            function_depth: 0,
+            strings: vec![],
+            calls: vec![],
+            frame: Frame::new(0),
+            mentions: vec![],
+            upcoming: vec![],
+            entry: None,
         }
     }
 
     /// Takes a Vector of Strings that represent the text of a program and compiles
     /// it into bytecode
-    pub fn compile(&mut self) -> Vec<u8> {
-        let program = self.assembly.join("\n");
-        let bytecode = self.assembler.assemble(&program);
-        match bytecode {
-            Ok(b) => { b },
-            Err(_e) => { vec![] }
+    pub fn compile(&mut self) -> Result<Vec<u8>, CompileError> {
+        let listing = self.listing();
+        let instructions = Compiler::check_assembly(&listing)?;
+        let program = listing.join("\n");
+        // The `Assembler` keeps its symbols and sections around, so start fresh every time
+        self.assembler = Assembler::new();
+        match self.assembler.assemble(&program) {
+            Ok(mut bytecode) => {
+                // The header tells the VM to skip the read-only section, but the `Assembler`
+                // leaves it out of the bytecode, so put it back after the header
+                let code_start = PIE_HEADER_LENGTH + 4;
+                bytecode.splice(code_start..code_start, self.assembler.ro.iter().cloned());
+                Ok(bytecode)
+            },
+            Err(errors) => {
+                let line = errors.iter().filter_map(|e| Compiler::line_for_error(e, &instructions, &listing)).next();
+                Err(CompileError::Assembler{ errors, line })
+            }
+        }
+    }
+
+    /// Parses every line of the assembly on its own, so a problem can be tied to a line.
+    /// The `Assembler` doesn't report lines it can't parse or labels it can't find, and
+    /// instead silently leaves them out of the bytecode.
+    fn check_assembly(listing: &[String]) -> Result<Vec<AssemblerInstruction>, CompileError> {
+        let mut instructions = vec![];
+        for (index, text) in listing.iter().enumerate() {
+            let line = AssemblyLine{ number: index + 1, text: text.to_string() };
+            let mut parsed = match assembly_program(CompleteStr(text)) {
+                Ok((remainder, ref mut parsed)) if remainder.trim().is_empty() && parsed.instructions.len() == 1 => {
+                    parsed.instructions.remove(0)
+                },
+                _ => {
+                    return Err(CompileError::InvalidInstruction{ line, reason: "the assembler can't parse it".into() });
+                }
+            };
+            if parsed.opcode == Some(AssemblerToken::Op{ code: Opcode::IGL }) {
+                return Err(CompileError::InvalidInstruction{ line, reason: "unknown opcode".into() });
+            }
+            // See `emit_label` for why these need a dummy operand
+            if parsed.is_opcode() && !parsed.has_operands() && index + 1 < listing.len() {
+                let next_is_labelled = match assembly_program(CompleteStr(&listing[index + 1])) {
+                    Ok((_, next)) => next.instructions.iter().any(|i| i.is_label()),
+                    Err(_) => false,
+                };
+                if !next_is_labelled {
+                    let reason = "an instruction without operands must be followed by a labelled line".into();
+                    return Err(CompileError::InvalidInstruction{ line, reason });
+                }
+            }
+            instructions.push(parsed);
+        }
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            for operand in &[&instruction.operand1, &instruction.operand2, &instruction.operand3] {
+                if let Some(AssemblerToken::LabelUsage{ ref name }) = **operand {
+                    if !instructions.iter().any(|i| i.get_label_name().as_ref() == Some(name)) {
+                        let line = AssemblyLine{ number: index + 1, text: listing[index].to_string() };
+                        return Err(CompileError::UndefinedLabel{ label: name.to_string(), line });
+                    }
+                }
+            }
         }
+        Ok(instructions)
     }
 
+    /// Finds the line of assembly an `AssemblerError` is about, if it is about one
+    fn line_for_error(error: &AssemblerError, instructions: &[AssemblerInstruction], listing: &[String]) -> Option<AssemblyLine> {
+        let index = match *error {
+            AssemblerError::NoSegmentDeclarationFound{ instruction } |
+            AssemblerError::StringConstantDeclaredWithoutLabel{ instruction } => {
+                // The assembler counts the `LUI` it adds after a `LOAD` of a large number
+                let mut count = 0;
+                instructions.iter().position(|i| {
+                    count += if i.is_integer_needs_splitting() { 2 } else { 1 };
+                    count > instruction
+                })
+            },
+            AssemblerError::SymbolAlreadyDeclared => {
+                instructions.iter().enumerate().position(|(index, i)| {
+                    let name = i.get_label_name();
+                    name.is_some() && instructions[..index].iter().any(|earlier| earlier.get_label_name() == name)
+                })
+            },
+            _ => None,
+        };
+        index.map(|index| AssemblyLine{ number: index + 1, text: listing[index].to_string() })
+    }
+
+    /// Prints the assembly generated so far, one instruction per line
     pub fn print_asm(&self) {
-        for line in &self.assembly {
-            println!("{:#?}", line);
+        for line in &self.listing() {
+            println!("{}", line);
+        }
+    }
+
+    /// Returns the assembly generated so far, with the string literals in its `.data` section
+    fn listing(&self) -> Vec<String> {
+        let mut listing = self.assembly.clone();
+        if let Some(ref entry) = self.entry {
+            if let Some(code) = listing.iter().position(|line| line == ".code") {
+                let jump = vec!["EQ $0 $0".to_string(), format!("DJMPE @{}", entry)];
+                listing.splice(code + 1..code + 1, jump);
+            }
+        }
+        if listing.first().map(String::as_str) == Some(".data") {
+            listing.splice(1..1, self.data_lines());
+        }
+        listing
+    }
+
+    /// Returns the lines of `.data` that hold the string literals. A string is stored as its
+    /// UTF-8 bytes and a 0 byte that ends it, padded with 0s to a multiple of four bytes. Every
+    /// four bytes go in an `.integer` directive of their own, rather than the whole string in an
+    /// `.asciiz`: the `Assembler` counts each line of `.data` as four bytes of code when it works
+    /// out where the code labels are, and an `.asciiz` can't hold a `'` or a line break. The first
+    /// directive of a string has its label. The assembler skips directives without a label, so
+    /// the others get one too.
+    fn data_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (index, value) in self.strings.iter().enumerate() {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.push(0);
+            while bytes.len() % 4 != 0 {
+                bytes.push(0);
+            }
+            for (word_index, word) in bytes.chunks(4).enumerate() {
+                let label = if word_index == 0 {
+                    Compiler::string_label(index)
+                } else {
+                    format!("{}word{}", Compiler::string_label(index), word_index)
+                };
+                let value = i32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                lines.push(format!("{}: .integer #{}", label, value));
+            }
         }
+        lines
+    }
+
+    /// Returns the label of the string literal `value` in `.data`, adding the literal if it
+    /// isn't there yet. Every distinct literal is only stored once.
+    fn intern_string(&mut self, value: &str) -> String {
+        let index = match self.strings.iter().position(|string| string == value) {
+            Some(index) => index,
+            None => {
+                self.strings.push(value.to_string());
+                self.strings.len() - 1
+            }
+        };
+        Compiler::string_label(index)
+    }
+
+    /// The label of the string literal at `index` of `strings`
+    fn string_label(index: usize) -> String {
+        format!("string{}", index)
     }
 
     pub fn print_used_registers(&self) {
         println!("--------------------");
         println!("|  Used Registers  |");
         println!("--------------------");
-        for r in &self.used_registers {
-            println!("{:#?}", r);
+        for operand in &self.used_registers {
+            println!("{}", operand);
         }
     }
 
@@ -86,9 +276,21 @@ impl Compiler {
     }
 
     pub fn get_variable(&self, variable: &str) -> Option<u8> {
-        for scope in self.scopes.iter().rev() {
+        match self.find_variable(variable) {
+            Some((_, Location::Register(register))) => Some(register),
+            _ => None,
+        }
+    }
+
+    /// Finds the innermost variable called `variable`. Returns the index of its `Scope` and
+    /// where its value is.
+    fn find_variable(&self, variable: &str) -> Option<(usize, Location)> {
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
             if let Some(register) = scope.get_variable(variable) {
-                return Some(register);
+                return Some((index, Location::Register(register)));
+            }
+            if let Some(offset) = scope.get_slot(variable) {
+                return Some((index, Location::Slot(offset)));
             }
         }
         None
@@ -108,6 +310,9 @@ impl Compiler {
     pub fn remove_scope(&mut self) {
         if let Some(newest_scope) = self.scopes.pop() {
             self.free_registers.extend(newest_scope.get_registers());
+            for &offset in newest_scope.slots.values() {
+                self.kill_slot(offset);
+            }
             self.scope_pointer -= 1;
         }
     }
@@ -121,417 +326,2384 @@ impl Compiler {
         self.scopes.last_mut().unwrap()
     }
 
+    /// Writes the section headers every program starts with
     pub fn write_prologue(&mut self) {
-        
+        self.assembly.push(".data".into());
+        self.assembly.push(".code".into());
     }
-}
 
-impl Visitor for Compiler {
-    fn visit_token(&mut self, node: &Token) {
-        match node {
-            &Token::AdditionOperator => {
-                // TODO: Need to clean this up. Remove the unwraps.
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("ADD ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::SubtractionOperator => {
-                // TODO: Need to clean this up. Remove the unwraps.
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("SUB ${} ${} ${}", right_register, left_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::MultiplicationOperator => {
-                // TODO: Need to clean this up. Remove the unwraps.
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("MUL ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::DivisionOperator => {
-                // TODO: Need to clean this up. Remove the unwraps.
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("DIV ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::GreaterThan => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("GT ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::LessThan => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("LT ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::GreaterThanOrEqual => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("GTE ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::LessThanOrEqual => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("LTE ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::EqualTo => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("EQ ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::LogicalAnd => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("AND ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::LogicalNot => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let line = format!("NOT ${} ${}", left_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-            },
-            &Token::LogicalOr => {
-                let result_register = self.free_registers.pop().unwrap();
-                let left_register = self.used_registers.pop().unwrap();
-                let right_register = self.used_registers.pop().unwrap();
-                let line = format!("OR ${} ${} ${}", left_register, right_register, result_register);
-                self.assembly.push(line);
-                self.used_registers.push(result_register);
-                self.free_registers.push(left_register);
-                self.free_registers.push(right_register);
-            },
-            &Token::Assignment => {
+    /// Makes the program start at the code generated after this, instead of at the top of
+    /// `.code`. The REPL runs each input on a VM that still has the registers and stack the
+    /// inputs before it left, so only the code of the newest input should run. The functions
+    /// defined by earlier inputs are still there to be called.
+    pub fn mark_entry(&mut self) {
+        let label = self.next_label("entry");
+        self.emit_label(&label);
+        self.entry = Some(label);
+    }
 
-            },
-            &Token::Integer{ value } => {
-                if self.in_return {
-                    let line = format!("LOAD $31 #{}", value);
-                    self.assembly.push(line);
-                } else {
-                    let next_register = self.free_registers.pop().unwrap();
-                    let line = format!("LOAD ${} #{}", next_register, value);
-                    {
-                        let mut current_scope = self.current_scope();
-                        current_scope.used_registers.push(next_register);
-                    }
-                    self.assembly.push(line);
-                }
+    /// Visits a statement, such as a top-level expression, and releases the register its
+    /// value ended up in. That register is remembered so the REPL can show the value.
+    pub fn visit_statement(&mut self, node: &Token) -> Result<(), CodegenError> {
+        let outer_mentions = mem::replace(&mut self.mentions, liveness::mentioned_names(node));
+        self.visit_token(node)?;
+        self.mentions = outer_mentions;
+        self.result_register = if self.used_registers.is_empty() {
+            None
+        } else {
+            Some(self.pop_operand(node)?)
+        };
+        // Anything else left on the stack belongs to a statement that didn't consume it. This
+        // also pops whatever of it was spilled, so the Iridium stack is back where it started.
+        while !self.used_registers.is_empty() {
+            let register = self.pop_operand(node)?;
+            self.free_register(register);
+        }
+        if let Some(register) = self.result_register {
+            self.free_register(register);
+        }
+        Ok(())
+    }
 
-            },
-            &Token::Float{ value } => {
-                let next_register = self.free_registers.pop().unwrap();
-                let line = format!("LOAD ${} #{}", next_register, value);
-                self.used_registers.push(next_register);
-                self.assembly.push(line);
-            },
-            &Token::Identifier{ ref values } => {
-                for value in values {
-                    self.identifier_buffer.push(value.to_string());
+    /// Visits a list of statements, such as the body of a function. Once a statement has been
+    /// visited, the variables of the current scope that no later statement mentions are
+    /// removed, and their registers go back to the free list. Variables mentioned in
+    /// `live_out` are kept.
+    fn visit_statements(&mut self, statements: &[Token], live_out: &[&Token]) -> Result<(), CodegenError> {
+        let last_uses = liveness::last_uses(statements, live_out);
+        self.upcoming.push((statements.iter().map(liveness::mentioned_names).collect(), 0));
+        for (index, (statement, dead_variables)) in statements.iter().zip(last_uses).enumerate() {
+            if let Some(upcoming) = self.upcoming.last_mut() {
+                upcoming.1 = index;
+            }
+            self.visit_statement(statement)?;
+            for name in &dead_variables {
+                if let Some(register) = self.current_scope().remove_variable(name) {
+                    self.free_register(register);
                 }
-            },
-            &Token::If{ ref expr, ref body} => {
-
-            },
-            &Token::Elif{ ref expr, ref body} => {
-
-            },
-            &Token::Else{ ref body } => {
-
-            },
-            &Token::Factor{ ref value } => {
-                self.visit_token(value);
-            },
-            &Token::Term{ ref left, ref right } => {
-                // If we are doing a variable assignment, we need to handle it a bit differently
-                if right.len() > 0 {
-                    if right[0].0 == Token::Assignment {
-                        self.visit_token(left);
-                        self.visit_token(&right[0].1);
-                        let identifier = self.identifier_buffer.pop().unwrap();
-                        let mut current_scope = self.pop_current_scope().unwrap();
-                        self.new_variable(&identifier, 31);
-                    }
-                } else {
-                    self.visit_token(left);
-                    for factor in right {
-                        self.visit_token(&factor.1);
-                        self.visit_token(&factor.0);
+                if let Some(offset) = self.current_scope().remove_slot(name) {
+                    self.kill_slot(offset);
+                }
+                if let Some(registers) = self.current_scope().remove_list(name) {
+                    for register in registers {
+                        self.free_register(register);
                     }
                 }
-            },
-            &Token::FunctionName{ ref name } => {
+            }
+            self.pop_dead_slots();
+        }
+        self.upcoming.pop();
+        Ok(())
+    }
 
-            },
-            &Token::FunctionArgs{ ref args } => {
-                for arg in args {
-                    let next_register = self.free_registers.pop().unwrap();
-                    self.new_variable(arg, next_register);
-                }
-            },
-            &Token::FunctionBody{ ref expressions } => {
-                for expr in expressions {
-                    self.visit_token(expr);
+    /// Emits an `if`, followed by any number of `elif`s and an optional `else`. The conditions
+    /// are tested in order and only the body of the first one that holds runs. If none of
+    /// them hold, the body of the `else` runs.
+    fn emit_conditional(&mut self, branches: &[&Token]) -> Result<(), CodegenError> {
+        for branch in branches {
+            self.declare_assigned(branch)?;
+        }
+        let end_label = self.next_label("endif");
+        // Every branch ends with the variables where they were before the first condition
+        let layout = self.layout();
+        let mut has_else = false;
+        for (index, branch) in branches.iter().enumerate() {
+            let (expr, body) = match **branch {
+                Token::If{ ref expr, ref body, .. } if index == 0 => (expr, body),
+                Token::Elif{ ref expr, ref body, .. } if index > 0 => (expr, body),
+                Token::Else{ ref body, .. } if index > 0 && index + 1 == branches.len() => {
+                    self.emit_block(body)?;
+                    self.reconcile(branch, &layout)?;
+                    has_else = true;
+                    continue;
+                },
+                _ => {
+                    return Err(CodegenError::DanglingBranch{ token: (*branch).clone() });
                 }
-            },
-            &Token::Function{ ref name, ref args, ref body, ref return_statement } => {
-                self.new_scope();
-                self.visit_token(args);
-                let mut line = format!("{}:", Box::new(name));
-                self.assembly.push(line);
-                self.visit_token(&body);
-                self.visit_token(&return_statement);
-                self.assembly.push(format!("RET"));
-            },
-            &Token::FunctionCall{ ref name, ref parameters } => {
-                match *parameters.clone() {
-                    Token::FunctionArgs{ args } => {
-                        for arg in args {
-                            let register = self.get_variable(&arg);
-                            let line = format!("PUSH {}", register.unwrap());
-                            self.assembly.push(line);
-                        }
+            };
+            let next_label = self.next_label("nextbranch");
+            self.visit_token(expr)?;
+            let condition = self.pop_operand(branch)?;
+            self.emit_jump_if_false(condition, &next_label);
+            self.free_register(condition);
+            let skipped = self.layout();
+            self.emit_block(body)?;
+            self.reconcile(branch, &layout)?;
+            self.emit_jump(&end_label);
+            self.emit_label(&next_label);
+            self.adopt(skipped);
+        }
+        if !has_else {
+            if let Some(last) = branches.last() {
+                self.reconcile(last, &layout)?;
+            }
+        }
+        self.emit_label(&end_label);
+        Ok(())
+    }
+
+    /// Binds the names assigned to in the body of the conditional or loop `node` that aren't
+    /// variables yet, so that like in Python they are still defined after it. They start out as
+    /// `None`, which is what they are if the body doesn't run. A list lives in one register per
+    /// element, so every list assigned to the same name has to be as long, and starts out as
+    /// that many `None`s.
+    fn declare_assigned(&mut self, node: &Token) -> Result<(), CodegenError> {
+        let mut assignments = vec![];
+        Compiler::assigned_names(node, &mut assignments);
+        for (index, &(ref name, length, token)) in assignments.iter().enumerate() {
+            let declared = assignments[..index].iter().any(|(earlier, _, _)| earlier == name);
+            if declared || self.find_variable(name).is_some() || self.get_list(name).is_some() {
+                continue;
+            }
+            let length = match length {
+                Some(length) => length,
+                None => {
+                    let register = self.allocate_register(node)?;
+                    self.assembly.push(format!("LOAD ${} #0", register));
+                    self.new_variable(name, register);
+                    continue;
+                },
+            };
+            for &(ref other, found, token) in &assignments[index + 1..] {
+                match found {
+                    Some(found) if other == name && found != length => {
+                        let (name, expected, found, token) = (name.to_string(), length as u32, found as u32, token.clone());
+                        return Err(CodegenError::ListLengthMismatch{ name, expected, found, token });
                     },
-                    _ => {}
-                };
-                let mut line = format!("CALL @{}", name);
-                self.assembly.push(line);
-            },
-            &Token::ReturnStatement{ ref parameters } => {
-                self.in_return = true;
-                self.visit_token(&parameters);
-                self.in_return = false;
-            },
-            &Token::ReturnArgs{ ref args } => {
-                for arg in args {
-                    self.visit_token(arg);
+                    _ => {},
                 }
-            },
-            &Token::ForLoop{ ref start, ref body } => {
-
-            },
-            &Token::ForLoopStart{ ref variable_name, ref collection_name } => {
+            }
+            let mut registers = vec![];
+            for _ in 0..length {
+                let register = match self.allocate_register(node) {
+                    Ok(register) => register,
+                    Err(_) => return Err(Compiler::list_too_long(token, name, length, registers.len())),
+                };
+                self.assembly.push(format!("LOAD ${} #0", register));
+                registers.push(register);
+            }
+            self.current_scope().new_list(name, registers);
+        }
+        Ok(())
+    }
 
-            },
-            &Token::ForLoopBody{ ref expressions } => {
+    /// Gives every top-level variable that a function reads a register of its own, before
+    /// anything is compiled. A function takes the registers for its locals from the free list
+    /// as it is at the function's definition, which can be before the variable is assigned, and
+    /// only the caller's registers are saved across a call. Without this a local could take
+    /// the variable's register and be read in its place by any function called from there.
+    fn reserve_globals(&mut self, node: &Token, statements: &[Token]) -> Result<(), CodegenError> {
+        let mut assignments = vec![];
+        let mut read = vec![];
+        for statement in statements {
+            Compiler::assigned_names(statement, &mut assignments);
+            if let Token::Function{ ref parameters, ref body, .. } = *statement {
+                let mut locals = liveness::mentioned_names(parameters);
+                if let Token::FunctionBody{ ref expressions, .. } = **body {
+                    let mut assigned = vec![];
+                    for statement in expressions {
+                        Compiler::assigned_names(statement, &mut assigned);
+                    }
+                    locals.extend(assigned.into_iter().map(|(name, _, _)| name));
+                }
+                read.extend(liveness::mentioned_names(body).into_iter().filter(|name| !locals.contains(name)));
+            }
+        }
+        for (name, length, _) in assignments {
+            // Lists can't be used as values, so a function never reads one
+            if length.is_none() && read.contains(&name) && self.get_variable(&name).is_none() {
+                let register = self.allocate_register(node)?;
+                self.assembly.push(format!("LOAD ${} #0", register));
+                self.new_variable(&name, register);
+            }
+        }
+        Ok(())
+    }
 
-            },
-            &Token::WhileLoop{ ref start, ref body } => {
+    /// Emits the body of a conditional or loop. The variables assigned in it were declared by
+    /// `declare_assigned`, so the ones left in its scope are loop variables and lists, whose
+    /// registers are freed once it ends.
+    fn emit_block(&mut self, body: &[Token]) -> Result<(), CodegenError> {
+        self.new_scope();
+        self.visit_statements(body, &[])?;
+        self.remove_scope();
+        Ok(())
+    }
 
-            },
-            &Token::WhileLoopStart{ ref expression } => {
+    /// Saves enough of the `Compiler`'s state to undo statements visited after this, such as
+    /// a line in the REPL that failed to compile
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            free_registers: self.free_registers.clone(),
+            used_registers: self.used_registers.clone(),
+            assembly_length: self.assembly.len(),
+            scopes: self.scopes.clone(),
+            scope_pointer: self.scope_pointer,
+            result_register: self.result_register,
+            functions: self.functions.clone(),
+            function_depth: self.function_depth,
+            strings_length: self.strings.len(),
+            calls_length: self.calls.len(),
+            frame: self.frame.clone(),
+            mentions: self.mentions.clone(),
+            upcoming_length: self.upcoming.len(),
+            entry: self.entry.clone(),
+        }
+    }
 
-            },
-            &Token::WhileLoopBody{ ref expressions } => {
+    /// Undoes everything visited since `snapshot` was taken
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.free_registers = snapshot.free_registers;
+        self.used_registers = snapshot.used_registers;
+        self.assembly.truncate(snapshot.assembly_length);
+        self.scopes = snapshot.scopes;
+        self.scope_pointer = snapshot.scope_pointer;
+        self.result_register = snapshot.result_register;
+        self.functions = snapshot.functions;
+        self.function_depth = snapshot.function_depth;
+        self.strings.truncate(snapshot.strings_length);
+        self.calls.truncate(snapshot.calls_length);
+        self.frame = snapshot.frame;
+        self.mentions = snapshot.mentions;
+        self.upcoming.truncate(snapshot.upcoming_length);
+        self.entry = snapshot.entry;
+    }
 
-            },
-            &Token::Eof => {
+    /// Saves where every variable is, so `reconcile` can put them back there
+    fn layout(&self) -> Layout {
+        Layout {
+            scopes: self.scopes.clone(),
+            frame: self.frame.clone(),
+            free_registers: self.free_registers.clone(),
+        }
+    }
 
-            },
-            &Token::ListInterior{ ref body } => {
+    /// Makes `layout` the current one without emitting anything. Used after a jump, since the
+    /// code that follows it is reached from somewhere else.
+    fn adopt(&mut self, layout: Layout) {
+        self.scopes = layout.scopes;
+        self.frame = layout.frame;
+        self.free_registers = layout.free_registers;
+    }
 
-            },
-            &Token::List { ref elements } => {
+    /// Emits what puts every variable back where it was when `layout` was saved: slots that
+    /// differ are popped, the variables the layout has in slots are pushed again, and the rest
+    /// are moved back into their registers. Used where paths through the code meet, such as
+    /// the end of a branch or of a loop body, since the code after that only knows one place
+    /// to look for each variable.
+    fn reconcile(&mut self, token: &Token, layout: &Layout) -> Result<(), CodegenError> {
+        let common = self.frame.slots.iter().zip(&layout.frame.slots).take_while(|&(current, saved)| current == saved).count();
+        while self.frame.slots.len() > common + 1 {
+            self.unwind_slot(token)?;
+        }
+        // Once the first slot that differs is on top, it can be swapped without a free register
+        if self.frame.slots.len() > common && !(self.free_registers.is_empty() && self.swap_top_slot(layout)) {
+            self.unwind_slot(token)?;
+        }
+        let pushed = self.frame.slots.len();
+        for (index, slot) in layout.frame.slots.iter().enumerate().skip(pushed) {
+            let offset = Frame::offset(index);
+            let owner = layout.scopes.iter().position(|scope| scope.slots.values().any(|&o| o == offset));
+            let register = match (slot, owner) {
+                (Slot::Variable(name), Some(owner)) => self.scopes[owner].get_variable(name),
+                _ => None,
+            };
+            // A dead slot only needs to take up room
+            self.emit_push(register.unwrap_or(ZERO_REGISTER));
+        }
 
-            },
-            &Token::Dictionary { ref keys, ref values } => {
+        let mut moves = vec![];
+        for (current, saved) in self.scopes.iter().zip(&layout.scopes) {
+            for (name, &destination) in &saved.variables {
+                if let Some(source) = current.get_variable(name) {
+                    if source != destination {
+                        moves.push((source, destination));
+                    }
+                }
+            }
+        }
+        // The order the moves are made in has to be the same every time
+        moves.sort();
+        self.emit_parallel_move(moves);
+        self.adopt(layout.clone());
+        Ok(())
+    }
 
-            },
-            &Token::DictionaryKeyValuePair { ref key, ref value } => {
+    /// Swaps the value in the top slot with the variable `layout` has in that slot, which has to
+    /// be in a register. Returns whether it could.
+    fn swap_top_slot(&mut self, layout: &Layout) -> bool {
+        let offset = self.frame.bp_offset();
+        let name = match layout.frame.slots.get(self.frame.slots.len().wrapping_sub(1)) {
+            Some(Slot::Variable(name)) => name.to_string(),
+            _ => return false,
+        };
+        let owner = match layout.scopes.iter().position(|scope| scope.get_slot(&name) == Some(offset)) {
+            Some(owner) => owner,
+            None => return false,
+        };
+        let register = match self.scopes[owner].get_variable(&name) {
+            Some(register) => register,
+            None => return false,
+        };
+        self.emit_pop(RETURN_REGISTER);
+        self.emit_push(register);
+        self.emit_move(RETURN_REGISTER, register);
+        if let Some(Slot::Variable(top)) = self.frame.pop() {
+            if let Some(scope) = self.scopes.iter_mut().rev().find(|scope| scope.get_slot(&top) == Some(offset)) {
+                scope.reload_variable(&top, register);
+            }
+        }
+        let offset = self.frame.push(Slot::Variable(name.to_string()));
+        self.scopes[owner].spill_variable(&name, offset);
+        true
+    }
 
-            },
-            &Token::Expression{ ref left, ref right } => {
-                self.visit_token(left);
-                for term in right {
-                    self.visit_token(&term.1);
-                    self.visit_token(&term.0);
-                }
-            },
-            &Token::Program{ ref expressions } => {
-                self.assembly.push(".data".into());
-                self.assembly.push(".code".into());
-                for expression in expressions {
-                    self.visit_token(expression);
-                }
-                self.assembly.push("HLT".into());
+    /// Emits the `(source, destination)` moves as if they all happened at once, so that no
+    /// move overwrites a register another one still has to read. A cycle of moves is broken
+    /// by copying one of its registers into the return register.
+    fn emit_parallel_move(&mut self, mut moves: Vec<(u8, u8)>) {
+        while !moves.is_empty() {
+            let ready = moves.iter().position(|&(_, destination)| moves.iter().all(|&(source, _)| source != destination));
+            match ready {
+                Some(index) => {
+                    let (source, destination) = moves.remove(index);
+                    self.emit_move(source, destination);
+                },
+                None => {
+                    let (_, destination) = moves[0];
+                    self.emit_move(destination, RETURN_REGISTER);
+                    for pending in moves.iter_mut().filter(|&&mut (source, _)| source == destination) {
+                        pending.0 = RETURN_REGISTER;
+                    }
+                },
             }
         }
     }
-}
 
-mod tests {
-    use super::*;
-    use nom::types::CompleteStr;
-    use program_parsers::program;
+    /// The register holding the value of the last statement passed to `visit_statement`, if
+    /// that statement produced a value
+    pub fn result_register(&self) -> Option<u8> {
+        self.result_register
+    }
 
-    fn generate_test_program(expr: &str) -> Token {
-        let source = CompleteStr(expr);
-        let result = program(source);
-        let (_, tree) = result.unwrap();
-        tree
+    /// Checks if a function with this name has been defined
+    pub fn has_function(&self, name: &str) -> bool {
+        self.parameter_count(name).is_some()
     }
 
-    #[test]
-    fn test_visit_addition_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("1+2");
-        compiler.visit_token(&test_program);
+    /// How many parameters the function `name` takes, if it has been defined
+    fn parameter_count(&self, name: &str) -> Option<usize> {
+        self.functions.iter().find(|(function, _)| function == name).map(|&(_, count)| count)
     }
 
-    #[test]
-    fn test_visit_subtraction_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2-1");
-        compiler.visit_token(&test_program);
+    /// Returns a new label that starts with `prefix`. Iridium labels may only contain
+    /// letters and digits.
+    fn next_label(&mut self, prefix: &str) -> String {
+        self.label_counter += 1;
+        format!("{}{}", prefix, self.label_counter)
     }
 
-    #[test]
-    fn test_visit_multiplication_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2*1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// The label a function's code starts at. The prefix keeps user functions from
+    /// colliding with the labels the `Compiler` generates.
+    fn function_label(name: &str) -> String {
+        format!("fn{}", name)
     }
 
-    #[test]
-    fn test_visit_division_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2/1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Emits a label for the next instruction. Iridium has no label-only lines, so the
+    /// label goes on a `NOP`. The assembler stops at an instruction with no operands that is
+    /// followed by an unlabelled line, which is why it gets a dummy one.
+    fn emit_label(&mut self, label: &str) {
+        self.assembly.push(format!("{}: NOP $0", label));
     }
 
-    #[test]
-    fn test_visit_greater_than_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2>1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Emits an unconditional jump to `label`. `JMP` needs the target in a register, so this
+    /// sets the equal flag with a comparison that always holds and uses `DJMPE` instead.
+    fn emit_jump(&mut self, label: &str) {
+        self.assembly.push("EQ $0 $0".into());
+        self.assembly.push(format!("DJMPE @{}", label));
     }
 
-    #[test]
-    fn test_visit_less_than_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2<1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Emits a `PUSH` of `register`. The VM only reads one operand of a `PUSH` or `POP` and
+    /// then decodes the last two bytes of the instruction as an instruction of their own. They
+    /// are filled with a `JMPF` by the zero register, which does nothing.
+    fn emit_push(&mut self, register: u8) {
+        let line = format!("PUSH ${} ${} ${}", register, u8::from(Opcode::JMPF), ZERO_REGISTER);
+        self.assembly.push(line);
     }
 
-    #[test]
-    fn test_visit_less_than_or_equal_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2<=1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Emits a `POP` into `register`. See `emit_push` for why it has three operands.
+    fn emit_pop(&mut self, register: u8) {
+        let line = format!("POP ${} ${} ${}", register, u8::from(Opcode::JMPF), ZERO_REGISTER);
+        self.assembly.push(line);
     }
 
-    #[test]
-    fn test_visit_greater_than_or_equal_token() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("2>=1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Emits a jump to `label` that is taken when `register` holds 0, which is false
+    fn emit_jump_if_false(&mut self, register: u8, label: &str) {
+        self.assembly.push(format!("EQ ${} ${}", register, ZERO_REGISTER));
+        self.assembly.push(format!("DJMPE @{}", label));
     }
 
-    #[test]
-    fn test_nested_operators() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("(4*3)-1");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Takes a register off the free list. `token` is what the register is needed for. If
+    /// there are no free registers, one is freed by spilling a variable, or failing that an
+    /// operand. Only when every register holds a variable the statement mentions or a value
+    /// that is being worked on does this fail.
+    fn allocate_register(&mut self, token: &Token) -> Result<u8, CodegenError> {
+        if let Some(register) = self.free_registers.pop() {
+            return Ok(register);
+        }
+        if let Some(register) = self.spill_variable() {
+            return Ok(register);
+        }
+        match self.spill_operand() {
+            Some(register) => Ok(register),
+            None => Err(CodegenError::RegisterExhaustion{ token: token.clone() }),
+        }
     }
 
-    #[test]
-    fn test_variable_assignment() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("x = 4");
-        compiler.visit_token(&test_program);
-        let bytecode = compiler.compile();
+    /// Picks the variable to spill when a register is needed: the one in a register whose
+    /// next use is furthest away. Only variables of the current function can be spilled, and
+    /// not ones the statement being visited mentions, since their registers may be operands.
+    /// Returns the index of its `Scope`, its name and its register.
+    fn spill_candidate(&self) -> Option<(usize, String, u8)> {
+        let mut candidates = vec![];
+        for index in self.frame.first_scope..self.scopes.len() {
+            for (name, &register) in &self.scopes[index].variables {
+                if self.mentions.contains(name) || self.used_registers.contains(&Operand::Register(register)) {
+                    continue;
+                }
+                candidates.push((self.next_use(name), register, index, name.to_string()));
+            }
+        }
+        // Ties go to the highest register, so the same program always spills the same way
+        candidates.into_iter().max().map(|(_, register, index, name)| (index, name, register))
     }
 
-    #[test]
-    fn test_function_call_with_args() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("a = 1\ndef testfunc(a):\n\t3+4\n\treturn 0;");
-        compiler.visit_token(&test_program);
-        println!("{:#?}", compiler.assembly);   
+    /// How many statements away the next one that mentions `name` is. A statement that
+    /// contains the one being visited counts too, since it may be a loop that runs it again.
+    fn next_use(&self, name: &str) -> usize {
+        let mut distance = 0;
+        for (depth, &(ref mentions, position)) in self.upcoming.iter().rev().enumerate() {
+            let start = if depth == 0 { position + 1 } else { position };
+            if let Some(found) = mentions[start..].iter().position(|names| names.iter().any(|n| n == name)) {
+                return distance + found;
+            }
+            distance += mentions.len() - start;
+        }
+        usize::MAX
     }
-    #[test]
-    fn test_function_declaration() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("def testfunc():\n\t3+4\n\treturn 0;");
-        compiler.visit_token(&test_program);
+
+    /// Pushes the variable `spill_candidate` picks into a new slot of the `Frame`, and returns
+    /// the register it was in
+    fn spill_variable(&mut self) -> Option<u8> {
+        let (index, name, register) = self.spill_candidate()?;
+        self.emit_push(register);
+        let offset = self.frame.push(Slot::Variable(name.to_string()));
+        self.scopes[index].spill_variable(&name, offset)
     }
 
-    #[test]
-    fn test_function_call_assignment() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program("x = testfunc()");
-        compiler.visit_token(&test_program);
+    /// Pushes the oldest operand on the operand stack that is in a register of its own onto
+    /// the Iridium stack, and returns that register. Operands are used in the opposite order
+    /// they were computed in, so the oldest one is needed last. Spilling oldest first also
+    /// means the Iridium stack always has the operands in the order `pop_operand` reloads them.
+    fn spill_operand(&mut self) -> Option<u8> {
+        let index = self.used_registers.iter().position(|operand| {
+            match *operand {
+                Operand::Register(register) => self.get_variable_name(register).is_none(),
+                Operand::Spilled => false,
+            }
+        })?;
+        let register = match self.used_registers[index] {
+            Operand::Register(register) => register,
+            Operand::Spilled => return None,
+        };
+        self.emit_push(register);
+        self.frame.push(Slot::Operand);
+        self.used_registers[index] = Operand::Spilled;
+        Some(register)
     }
 
-    #[test]
-    fn test_function_return_values() {
-        let mut compiler = Compiler::new();
-        let test_program = generate_test_program(
-r#"
-def test():
-    return 1;
+    /// Pops the slot at `index` of the `Frame` into a register and returns the register. The
+    /// slots above it are popped first. Anything spilled would be pushed on top of the slot,
+    /// so this only uses free registers, except for a slot on top, which can be swapped with a
+    /// variable that is in a register.
+    fn reload_slot(&mut self, token: &Token, index: usize) -> Result<u8, CodegenError> {
+        while self.frame.slots.len() > index + 1 {
+            self.unwind_slot(token)?;
+        }
+        let offset = self.frame.bp_offset();
+        if let Some(register) = self.free_registers.pop() {
+            self.frame.pop();
+            self.emit_pop(register);
+            return Ok(register);
+        }
+        let (scope, name, register) = match self.spill_candidate() {
+            Some(candidate) => candidate,
+            None => return Err(CodegenError::RegisterExhaustion{ token: token.clone() }),
+        };
+        self.emit_pop(RETURN_REGISTER);
+        self.emit_push(register);
+        self.emit_move(RETURN_REGISTER, register);
+        self.frame.slots[index] = Slot::Variable(name.to_string());
+        self.scopes[scope].spill_variable(&name, offset);
+        Ok(register)
+    }
 
-y = test()
-"#
-        );
-        compiler.visit_token(&test_program);
-        println!("{:#?}", compiler.assembly);
+    /// Pops the top slot of the `Frame`. A variable or operand in it goes back into a register,
+    /// which has to be a free one, and a dead slot is thrown away.
+    fn unwind_slot(&mut self, token: &Token) -> Result<(), CodegenError> {
+        let offset = self.frame.bp_offset();
+        let register = match self.frame.slots.last() {
+            Some(&Slot::Dead) => RETURN_REGISTER,
+            Some(_) => match self.free_registers.pop() {
+                Some(register) => register,
+                None => return Err(CodegenError::RegisterExhaustion{ token: token.clone() }),
+            },
+            None => return Ok(()),
+        };
+        self.emit_pop(register);
+        match self.frame.pop() {
+            Some(Slot::Variable(name)) => {
+                if let Some(scope) = self.scopes.iter_mut().rev().find(|scope| scope.get_slot(&name) == Some(offset)) {
+                    scope.reload_variable(&name, register);
+                }
+            },
+            Some(Slot::Operand) => {
+                // Operands are spilled oldest first, so the newest spilled one is on top
+                if let Some(operand) = self.used_registers.iter_mut().rev().find(|operand| **operand == Operand::Spilled) {
+                    *operand = Operand::Register(register);
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Marks the slot at `offset` as no longer needed
+    fn kill_slot(&mut self, offset: i32) {
+        if let Some(slot) = self.frame.slots.get_mut(Frame::index(offset)) {
+            *slot = Slot::Dead;
+        }
+    }
+
+    /// Pops the dead slots on top of the `Frame`, so the ones below are quicker to reach
+    fn pop_dead_slots(&mut self) {
+        while self.frame.slots.last() == Some(&Slot::Dead) {
+            self.emit_pop(RETURN_REGISTER);
+            self.frame.pop();
+        }
+    }
+
+    /// Emits the pops that leave nothing of the `Frame` on the Iridium stack, each into
+    /// `register`. Used before a function returns, since `RET` pops the return address.
+    fn emit_frame_cleanup(&mut self, register: u8) {
+        for _ in 0..self.frame.slots.len() {
+            self.emit_pop(register);
+        }
+    }
+
+    /// Returns the register holding the variable `name`, popping it off the `Frame` if it was
+    /// spilled
+    fn load_variable(&mut self, token: &Token, name: &str) -> Result<u8, CodegenError> {
+        match self.find_variable(name) {
+            Some((_, Location::Register(register))) => Ok(register),
+            Some((scope, Location::Slot(offset))) => {
+                if scope < self.frame.first_scope {
+                    // The slot is in the frame of whatever called this function
+                    return Err(CodegenError::RegisterExhaustion{ token: token.clone() });
+                }
+                let register = self.reload_slot(token, Frame::index(offset))?;
+                self.scopes[scope].reload_variable(name, register);
+                Ok(register)
+            },
+            None if self.get_list(name).is_some() => {
+                Err(CodegenError::ListUsedAsValue{ name: name.to_string(), token: token.clone() })
+            },
+            None => Err(CodegenError::UndefinedVariable{ name: name.to_string(), token: token.clone() }),
+        }
+    }
+
+    /// Pushes a register holding a value onto the operand stack
+    fn push_operand(&mut self, register: u8) {
+        self.used_registers.push(Operand::Register(register));
+    }
+
+    /// Pops the register holding the next operand of `token` off the operand stack. An
+    /// operand that was spilled is popped off the Iridium stack into a register first.
+    fn pop_operand(&mut self, token: &Token) -> Result<u8, CodegenError> {
+        match self.used_registers.pop() {
+            Some(Operand::Register(register)) => Ok(register),
+            Some(Operand::Spilled) => {
+                match self.frame.slots.iter().rposition(|slot| *slot == Slot::Operand) {
+                    Some(index) => self.reload_slot(token, index),
+                    None => Err(CodegenError::OperandStackUnderflow{ token: token.clone() }),
+                }
+            },
+            None => Err(CodegenError::OperandStackUnderflow{ token: token.clone() }),
+        }
+    }
+
+    /// Returns a register to the free list, unless a variable still lives in it
+    fn free_register(&mut self, register: u8) {
+        let in_use = self.scopes.iter().any(|scope| scope.get_registers().contains(&register));
+        if !in_use && !self.free_registers.contains(&register) {
+            self.free_registers.push(register);
+        }
+    }
+
+    /// Pops the two operands of `token` off the operand stack, emits `opcode` for them and
+    /// pushes the register holding the result
+    fn emit_binary_operation(&mut self, token: &Token, opcode: &str) -> Result<(), CodegenError> {
+        let right_register = self.pop_operand(token)?;
+        let left_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        let line = format!("{} ${} ${} ${}", opcode, left_register, right_register, result_register);
+        self.assembly.push(line);
+        self.push_operand(result_register);
+        self.free_register(left_register);
+        self.free_register(right_register);
+        Ok(())
+    }
+
+    /// Pops the two operands of `token` off the operand stack and compares them with `opcode`.
+    /// Iridium's comparisons only set the equal flag, so this turns the flag into a 1 or 0 in a
+    /// register and pushes that.
+    fn emit_comparison(&mut self, token: &Token, opcode: &str) -> Result<(), CodegenError> {
+        let right_register = self.pop_operand(token)?;
+        let left_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        let true_label = self.next_label("true");
+        self.assembly.push(format!("LOAD ${} #1", result_register));
+        self.assembly.push(format!("{} ${} ${}", opcode, left_register, right_register));
+        self.assembly.push(format!("DJMPE @{}", true_label));
+        self.assembly.push(format!("LOAD ${} #0", result_register));
+        self.emit_label(&true_label);
+        self.push_operand(result_register);
+        self.free_register(left_register);
+        self.free_register(right_register);
+        Ok(())
+    }
+
+    /// Pops the two operands of `token` off the operand stack and pushes 1 or 0 for `and` or
+    /// `or` of them. Any value but 0 counts as true, so the operands can't just be combined with
+    /// `AND` or `OR`, which would make `2 and 1` 0.
+    fn emit_logical_operation(&mut self, token: &Token, is_and: bool) -> Result<(), CodegenError> {
+        let right_register = self.pop_operand(token)?;
+        let left_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        let done_label = self.next_label(if is_and { "and" } else { "or" });
+        // `and` is decided by the first false operand, and `or` by the first true one
+        let (decided, opcode) = if is_and { (0, "EQ") } else { (1, "NEQ") };
+        self.assembly.push(format!("LOAD ${} #{}", result_register, decided));
+        for operand_register in &[left_register, right_register] {
+            self.assembly.push(format!("{} ${} ${}", opcode, operand_register, ZERO_REGISTER));
+            self.assembly.push(format!("DJMPE @{}", done_label));
+        }
+        self.assembly.push(format!("LOAD ${} #{}", result_register, 1 - decided));
+        self.emit_label(&done_label);
+        self.push_operand(result_register);
+        self.free_register(left_register);
+        self.free_register(right_register);
+        Ok(())
+    }
+
+    /// Pops the two operands of `token` off the operand stack and divides them the way Python's
+    /// `//` and `%` do: the quotient is rounded down, and the remainder has the sign of the
+    /// divisor. Pushes the remainder if `remainder` is set, and the quotient if it isn't.
+    fn emit_floored_division(&mut self, token: &Token, remainder: bool) -> Result<(), CodegenError> {
+        let right_register = self.pop_operand(token)?;
+        let left_register = self.pop_operand(token)?;
+        let quotient_register = self.allocate_register(token)?;
+        let remainder_register = self.allocate_register(token)?;
+        let signs_register = self.allocate_register(token)?;
+        let done_label = self.next_label("floored");
+        // `DIV` rounds toward zero. The VM keeps the remainder, but no instruction can read it.
+        self.assembly.push(format!("DIV ${} ${} ${}", left_register, right_register, quotient_register));
+        self.assembly.push(format!("MUL ${} ${} ${}", quotient_register, right_register, remainder_register));
+        self.assembly.push(format!("SUB ${} ${} ${}", left_register, remainder_register, remainder_register));
+        // Rounding toward zero rounded up if there is a remainder and its sign isn't the divisor's
+        self.assembly.push(format!("EQ ${} ${}", remainder_register, ZERO_REGISTER));
+        self.assembly.push(format!("DJMPE @{}", done_label));
+        self.assembly.push(format!("XOR ${} ${} ${}", remainder_register, right_register, signs_register));
+        self.assembly.push(format!("GTE ${} ${}", signs_register, ZERO_REGISTER));
+        self.assembly.push(format!("DJMPE @{}", done_label));
+        self.assembly.push(format!("DEC ${}", quotient_register));
+        self.assembly.push(format!("ADD ${} ${} ${}", remainder_register, right_register, remainder_register));
+        self.emit_label(&done_label);
+        if remainder {
+            self.push_operand(remainder_register);
+            self.free_register(quotient_register);
+        } else {
+            self.push_operand(quotient_register);
+            self.free_register(remainder_register);
+        }
+        self.free_register(signs_register);
+        self.free_register(left_register);
+        self.free_register(right_register);
+        Ok(())
+    }
+
+    /// Pops the two operands of `token` off the operand stack and pushes the first raised to the
+    /// power of the second. Iridium has no instruction for it, so this multiplies in a loop. A
+    /// negative exponent runs the loop no times and gives 1.
+    fn emit_exponent(&mut self, token: &Token) -> Result<(), CodegenError> {
+        let exponent_register = self.pop_operand(token)?;
+        let base_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        self.assembly.push(format!("LOAD ${} #1", result_register));
+        let line = format!("MUL ${} ${} ${}", result_register, base_register, result_register);
+        self.emit_repeat(token, exponent_register, &line)?;
+        self.push_operand(result_register);
+        self.free_register(base_register);
+        self.free_register(exponent_register);
+        Ok(())
+    }
+
+    /// Pops the two operands of `token` off the operand stack and pushes the first shifted by
+    /// the number of bits in the second. `opcode` is `SHL` or `SHR`, which shift a register in
+    /// place by a number of bits that is part of the instruction. That number is written like a
+    /// register, since the assembler turns `$1` into a byte holding 1, so this shifts one bit at
+    /// a time in a loop. A negative shift doesn't shift at all.
+    fn emit_shift(&mut self, token: &Token, opcode: &str) -> Result<(), CodegenError> {
+        let bits_register = self.pop_operand(token)?;
+        let value_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        self.emit_move(value_register, result_register);
+        let line = format!("{} ${} $1", opcode, result_register);
+        self.emit_repeat(token, bits_register, &line)?;
+        self.push_operand(result_register);
+        self.free_register(value_register);
+        self.free_register(bits_register);
+        Ok(())
+    }
+
+    /// Emits a loop that runs `line` as many times as the value in `times_register`, and not at
+    /// all if that value isn't positive
+    fn emit_repeat(&mut self, token: &Token, times_register: u8, line: &str) -> Result<(), CodegenError> {
+        let counter_register = self.allocate_register(token)?;
+        let header_label = self.next_label("repeat");
+        let end_label = self.next_label("endrepeat");
+        // The count may be a variable, so count down a copy of it
+        self.emit_move(times_register, counter_register);
+        self.emit_label(&header_label);
+        self.assembly.push(format!("LTE ${} ${}", counter_register, ZERO_REGISTER));
+        self.assembly.push(format!("DJMPE @{}", end_label));
+        self.assembly.push(line.to_string());
+        self.assembly.push(format!("DEC ${}", counter_register));
+        self.emit_jump(&header_label);
+        self.emit_label(&end_label);
+        self.free_register(counter_register);
+        Ok(())
+    }
+
+    /// Pops the operand of `token` off the operand stack and pushes 1 if it is 0, and 0 if it
+    /// isn't. Iridium's `NOT` flips every bit, which turns 1 into -2 and so is still true, so
+    /// this compares the operand to the zero register instead.
+    fn emit_logical_not(&mut self, token: &Token) -> Result<(), CodegenError> {
+        let operand_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        let true_label = self.next_label("true");
+        self.assembly.push(format!("LOAD ${} #1", result_register));
+        self.assembly.push(format!("EQ ${} ${}", operand_register, ZERO_REGISTER));
+        self.assembly.push(format!("DJMPE @{}", true_label));
+        self.assembly.push(format!("LOAD ${} #0", result_register));
+        self.emit_label(&true_label);
+        self.push_operand(result_register);
+        self.free_register(operand_register);
+        Ok(())
+    }
+
+    /// Pops the operand of `token` off the operand stack and pushes its negation, which is the
+    /// operand subtracted from the zero register
+    fn emit_negation(&mut self, token: &Token) -> Result<(), CodegenError> {
+        let operand_register = self.pop_operand(token)?;
+        let result_register = self.allocate_register(token)?;
+        let line = format!("SUB ${} ${} ${}", ZERO_REGISTER, operand_register, result_register);
+        self.assembly.push(line);
+        self.push_operand(result_register);
+        self.free_register(operand_register);
+        Ok(())
+    }
+
+    /// Loads the integer `value` into `register`, keeping its lowest 32 bits. Iridium's `LOAD`
+    /// only takes 16 bits and doesn't sign extend them, so anything outside `0..=32767` is
+    /// built from its two halves with `LUI`, which shifts the register left by 16 bits and puts
+    /// the immediate in the bottom half.
+    fn emit_load(&mut self, register: u8, value: i64) {
+        let bits = value as u32;
+        if bits <= i16::MAX as u32 {
+            self.assembly.push(format!("LOAD ${} #{}", register, bits));
+            return;
+        }
+        self.assembly.push(format!("LOAD ${} #0", register));
+        self.assembly.push(format!("LUI ${} #{}", register, bits >> 16));
+        self.assembly.push(format!("LUI ${} #{}", register, bits & 0xFFFF));
+    }
+
+    /// Emits a call of the function `name` with `arguments`, and pushes the register its return
+    /// value ends up in. This is the calling convention:
+    ///
+    /// 1. The caller evaluates the arguments from left to right into registers.
+    /// 2. Every function uses whichever registers it likes, so the caller pushes each register
+    ///    that is still in use onto the Iridium stack.
+    /// 3. The caller pushes the arguments from left to right, and `CALL` pushes the return
+    ///    address.
+    /// 4. The function pops the return address, pops the arguments into the registers of its
+    ///    parameters from last to first, and pushes the return address back.
+    /// 5. The function leaves its return value in `$31`, and `RET` pops the return address.
+    /// 6. The caller pops the registers it saved, and copies `$31` into a register of its own
+    ///    before the next call overwrites it.
+    fn emit_call(&mut self, token: &Token, name: &str, arguments: &Token) -> Result<(), CodegenError> {
+        let count = match *arguments {
+            Token::Arguments{ ref values, .. } => values.len(),
+            _ => 0,
+        };
+        self.visit_token(arguments)?;
+        let mut argument_registers = vec![];
+        for _ in 0..count {
+            argument_registers.push(self.pop_operand(token)?);
+        }
+        argument_registers.reverse();
+        for &register in &argument_registers {
+            self.free_register(register);
+        }
+
+        // Whatever isn't free could be needed after the call, such as the counter of a loop
+        let saved: Vec<u8> = (0..ZERO_REGISTER).filter(|register| !self.free_registers.contains(register)).collect();
+        for &register in &saved {
+            self.emit_push(register);
+        }
+        for &register in &argument_registers {
+            self.emit_push(register);
+        }
+        self.assembly.push(format!("CALL @{}", Compiler::function_label(name)));
+        for &register in saved.iter().rev() {
+            self.emit_pop(register);
+        }
+        self.calls.push((name.to_string(), count, token.clone()));
+
+        let result_register = self.allocate_register(token)?;
+        self.emit_move(31, result_register);
+        self.push_operand(result_register);
+        Ok(())
+    }
+
+    /// Copies the value in `source` into `destination`. Iridium has no move instruction, so
+    /// this zeroes the destination and adds the source to it.
+    fn emit_move(&mut self, source: u8, destination: u8) {
+        if source == destination {
+            return;
+        }
+        self.assembly.push(format!("SUB ${} ${} ${}", destination, destination, destination));
+        self.assembly.push(format!("ADD ${} ${} ${}", source, destination, destination));
+    }
+
+    /// Binds the value on top of the operand stack to `identifier`. Assigning to an existing
+    /// variable overwrites its register, so code that already refers to it sees the new value.
+    fn assign_variable(&mut self, token: &Token, identifier: &str) -> Result<(), CodegenError> {
+        let value_register = self.pop_operand(token)?;
+        let scope = match self.find_variable(identifier) {
+            Some((_, Location::Register(register))) => {
+                self.emit_move(value_register, register);
+                self.free_register(value_register);
+                return Ok(());
+            },
+            Some((scope, Location::Slot(offset))) if scope >= self.frame.first_scope => {
+                // The old value isn't needed, so the variable can move into a register instead
+                self.scopes[scope].remove_slot(identifier);
+                self.kill_slot(offset);
+                scope
+            },
+            _ => self.scopes.len() - 1,
+        };
+        let register = if self.get_variable_name(value_register).is_some() {
+            // The value lives in another variable's register, so it needs a copy
+            let register = self.allocate_register(token)?;
+            self.emit_move(value_register, register);
+            register
+        } else {
+            value_register
+        };
+        self.scopes[scope].new_variable(identifier, register);
+        Ok(())
+    }
+
+    /// Evaluates the elements of a list literal and binds them to `identifier`. A list lives in
+    /// one register per element, which belong to the list until it goes out of scope.
+    fn assign_list(&mut self, token: &Token, identifier: &str, elements: &[Token]) -> Result<(), CodegenError> {
+        let mut registers = vec![];
+        for element in elements {
+            let register = self.list_element(token, element).map_err(|error| match error {
+                CodegenError::RegisterExhaustion{ .. } => Compiler::list_too_long(token, identifier, elements.len(), registers.len()),
+                error => error,
+            })?;
+            registers.push(register);
+        }
+        // A list first assigned in a conditional or loop was declared outside of it, and has to
+        // stay in its registers to still be there afterwards
+        let current = self.scopes.len() - 1;
+        let outer = self.scopes[self.frame.first_scope..current].iter().rev().find_map(|scope| scope.get_list(identifier));
+        if let (None, Some(old_registers)) = (self.scopes[current].get_list(identifier), outer) {
+            if old_registers.len() != registers.len() {
+                let (expected, found) = (old_registers.len() as u32, registers.len() as u32);
+                return Err(CodegenError::ListLengthMismatch{ name: identifier.to_string(), expected, found, token: token.clone() });
+            }
+            for (&register, &old_register) in registers.iter().zip(&old_registers) {
+                self.emit_move(register, old_register);
+                self.free_register(register);
+            }
+            return Ok(());
+        }
+        // The elements have to be evaluated before the old value goes, since they may use it
+        if let Some(register) = self.current_scope().remove_variable(identifier) {
+            self.free_register(register);
+        }
+        if let Some(old_registers) = self.current_scope().remove_list(identifier) {
+            for register in old_registers {
+                self.free_register(register);
+            }
+        }
+        self.current_scope().new_list(identifier, registers);
+        Ok(())
+    }
+
+    /// Evaluates one element of a list literal into a register of its own
+    fn list_element(&mut self, token: &Token, element: &Token) -> Result<u8, CodegenError> {
+        self.visit_token(element)?;
+        let register = self.pop_operand(token)?;
+        if self.get_variable_name(register).is_none() {
+            return Ok(register);
+        }
+        let copy = self.allocate_register(token)?;
+        self.emit_move(register, copy);
+        Ok(copy)
+    }
+
+    /// The error for a list of `length` elements of which only `limit` fit in the registers
+    /// that were left
+    fn list_too_long(token: &Token, name: &str, length: usize, limit: usize) -> CodegenError {
+        CodegenError::ListTooLong{ name: name.to_string(), length: length as u32, limit: limit as u32, token: token.clone() }
+    }
+
+    /// Finds the registers of the elements of the list variable `name`
+    fn get_list(&self, name: &str) -> Option<Vec<u8>> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(registers) = scope.get_list(name) {
+                return Some(registers);
+            }
+        }
+        None
+    }
+
+    /// Emits a loop over the list `collection_name`. The length of the list is loaded once, and
+    /// a counter goes from 0 up to it. On every iteration the element at the counter is copied
+    /// into `variable_name`, which is bound in a new `Scope` along with the body's variables.
+    fn emit_for_loop(&mut self, token: &Token, variable_name: &str, collection_name: &str, body: &Token) -> Result<(), CodegenError> {
+        let elements = match self.get_list(collection_name) {
+            Some(elements) => elements,
+            None => {
+                let name = collection_name.to_string();
+                if self.find_variable(collection_name).is_some() {
+                    return Err(CodegenError::NotAList{ name, token: token.clone() });
+                }
+                return Err(CodegenError::UndefinedVariable{ name, token: token.clone() });
+            }
+        };
+        self.declare_assigned(body)?;
+        let length = self.allocate_register(token)?;
+        let index = self.allocate_register(token)?;
+        let element = self.allocate_register(token)?;
+        self.assembly.push(format!("LOAD ${} #{}", length, elements.len()));
+        self.assembly.push(format!("LOAD ${} #0", index));
+
+        let header_label = self.next_label("for");
+        let end_label = self.next_label("endfor");
+        let layout = self.layout();
+        self.emit_label(&header_label);
+        self.assembly.push(format!("GTE ${} ${}", index, length));
+        self.assembly.push(format!("DJMPE @{}", end_label));
+        self.emit_select(token, index, &elements, element)?;
+        self.new_scope();
+        self.new_variable(variable_name, element);
+        self.visit_token(body)?;
+        self.remove_scope();
+        self.reconcile(token, &layout)?;
+        self.assembly.push(format!("INC ${}", index));
+        self.emit_jump(&header_label);
+        self.emit_label(&end_label);
+
+        self.free_register(element);
+        self.free_register(index);
+        self.free_register(length);
+        Ok(())
+    }
+
+    /// Copies the register in `elements` at the position held in `index` into `destination`.
+    /// Iridium can't pick a register at runtime, so this compares `index` to every position.
+    fn emit_select(&mut self, token: &Token, index: u8, elements: &[u8], destination: u8) -> Result<(), CodegenError> {
+        let done_label = self.next_label("selected");
+        let position = self.allocate_register(token)?;
+        for (i, element) in elements.iter().enumerate() {
+            let next_label = self.next_label("notelement");
+            self.assembly.push(format!("LOAD ${} #{}", position, i));
+            self.assembly.push(format!("NEQ ${} ${}", index, position));
+            self.assembly.push(format!("DJMPE @{}", next_label));
+            self.emit_move(*element, destination);
+            self.emit_jump(&done_label);
+            self.emit_label(&next_label);
+        }
+        self.emit_label(&done_label);
+        self.free_register(position);
+        Ok(())
+    }
+
+    /// Collects every assignment in `node`, looking inside the bodies of conditionals and loops
+    /// but not of function definitions. Each is the name assigned to, the number of elements if
+    /// it is given a list, and the assignment.
+    fn assigned_names<'a>(node: &'a Token, names: &mut Vec<(String, Option<usize>, &'a Token)>) {
+        match *node {
+            Token::BinaryOperation{ ref operator, ref left, ref right, .. } if **operator == Token::Assignment => {
+                if let Some(name) = Compiler::assignment_target(left) {
+                    names.push((name, Compiler::list_literal(right).map(<[Token]>::len), node));
+                }
+            },
+            Token::If{ ref body, .. } | Token::Elif{ ref body, .. } | Token::Else{ ref body, .. } |
+            Token::WhileLoopBody{ expressions: ref body, .. } |
+            Token::ForLoopBody{ expressions: ref body, .. } => {
+                for statement in body {
+                    Compiler::assigned_names(statement, names);
+                }
+            },
+            Token::Conditional{ ref branches, .. } => {
+                for branch in branches {
+                    Compiler::assigned_names(branch, names);
+                }
+            },
+            Token::WhileLoop{ ref body, .. } | Token::ForLoop{ ref body, .. } => {
+                Compiler::assigned_names(body, names);
+            },
+            _ => {},
+        }
+    }
+
+    /// The value of `node` if it is a number with any number of `-` and `+` in front of it, such
+    /// as `-5`. A float only counts if it is a whole number.
+    fn constant(node: &Token) -> Option<i64> {
+        match *node {
+            Token::Factor{ ref value, .. } => Compiler::constant(value),
+            Token::Integer{ value, .. } => Some(value),
+            Token::Float{ value, .. } if value.fract() == 0.0 => Some(value as i64),
+            Token::UnaryOperation{ ref operator, ref operand, .. } => match **operator {
+                Token::SubtractionOperator => Compiler::constant(operand).map(|value| -value),
+                Token::AdditionOperator => Compiler::constant(operand),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Digs the elements out of a list or tuple literal, if `node` is nothing more than one.
+    /// A tuple is stored the same way as a list.
+    fn list_literal(node: &Token) -> Option<&[Token]> {
+        match *node {
+            Token::List{ ref elements, .. } | Token::Tuple{ ref elements, .. } => Some(elements),
+            Token::Factor{ ref value, .. } => Compiler::list_literal(value),
+            _ => None,
+        }
+    }
+
+    /// Finds which variable, if any, lives in `register`
+    fn get_variable_name(&self, register: u8) -> Option<String> {
+        for scope in self.scopes.iter().rev() {
+            for (name, r) in &scope.variables {
+                if *r == register {
+                    return Some(name.to_string());
+                }
+            }
+        }
+        None
+    }
+
+    /// Digs the name being assigned to out of the left side of an assignment
+    fn assignment_target(node: &Token) -> Option<String> {
+        match *node {
+            Token::Factor{ ref value, .. } => Compiler::assignment_target(value),
+            Token::Identifier{ ref name, .. } => Some(name.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Visitor for Compiler {
+    fn visit_token(&mut self, node: &Token) -> Result<(), CodegenError> {
+        match *node {
+            Token::AdditionOperator => {
+                self.emit_binary_operation(node, "ADD")?;
+            },
+            Token::SubtractionOperator => {
+                self.emit_binary_operation(node, "SUB")?;
+            },
+            Token::MultiplicationOperator => {
+                self.emit_binary_operation(node, "MUL")?;
+            },
+            Token::DivisionOperator => {
+                self.emit_binary_operation(node, "DIV")?;
+            },
+            Token::FloorDivisionOperator => {
+                self.emit_floored_division(node, false)?;
+            },
+            Token::ModuloOperator => {
+                self.emit_floored_division(node, true)?;
+            },
+            Token::ExponentOperator => {
+                self.emit_exponent(node)?;
+            },
+            Token::GreaterThan => {
+                self.emit_comparison(node, "GT")?;
+            },
+            Token::LessThan => {
+                self.emit_comparison(node, "LT")?;
+            },
+            Token::GreaterThanOrEqual => {
+                self.emit_comparison(node, "GTE")?;
+            },
+            Token::LessThanOrEqual => {
+                self.emit_comparison(node, "LTE")?;
+            },
+            Token::EqualTo => {
+                self.emit_comparison(node, "EQ")?;
+            },
+            Token::NotEqualTo => {
+                self.emit_comparison(node, "NEQ")?;
+            },
+            Token::LogicalAnd => {
+                self.emit_logical_operation(node, true)?;
+            },
+            Token::LogicalNot => {
+                self.emit_logical_not(node)?;
+            },
+            Token::LogicalOr => {
+                self.emit_logical_operation(node, false)?;
+            },
+            Token::BitwiseAnd => {
+                self.emit_binary_operation(node, "AND")?;
+            },
+            Token::BitwiseOr => {
+                self.emit_binary_operation(node, "OR")?;
+            },
+            Token::BitwiseXor => {
+                self.emit_binary_operation(node, "XOR")?;
+            },
+            Token::BitwiseNot => {
+                let operand_register = self.pop_operand(node)?;
+                let result_register = self.allocate_register(node)?;
+                let line = format!("NOT ${} ${}", operand_register, result_register);
+                self.assembly.push(line);
+                self.push_operand(result_register);
+                self.free_register(operand_register);
+            },
+            Token::ShiftLeft => {
+                self.emit_shift(node, "SHL")?;
+            },
+            Token::ShiftRight => {
+                self.emit_shift(node, "SHR")?;
+            },
+            Token::Assignment => {
+
+            },
+            Token::Integer{ value, .. } => {
+                let next_register = self.allocate_register(node)?;
+                self.emit_load(next_register, value);
+                self.push_operand(next_register);
+            },
+            Token::Float{ value, .. } => {
+                // Registers only hold integers, so a float has to be a whole number. The lexer
+                // already made sure it fits.
+                if value.fract() != 0.0 {
+                    return Err(CodegenError::UnsupportedFloat{ value, token: node.clone() });
+                }
+                let next_register = self.allocate_register(node)?;
+                self.emit_load(next_register, value as i64);
+                self.push_operand(next_register);
+            },
+            Token::Boolean{ value, .. } => {
+                let next_register = self.allocate_register(node)?;
+                self.assembly.push(format!("LOAD ${} #{}", next_register, if value { 1 } else { 0 }));
+                self.push_operand(next_register);
+            },
+            Token::None{ .. } => {
+                let next_register = self.allocate_register(node)?;
+                self.assembly.push(format!("LOAD ${} #0", next_register));
+                self.push_operand(next_register);
+            },
+            Token::String{ ref value, .. } => {
+                // A string is the offset of its first byte in the read-only section
+                let label = self.intern_string(value);
+                let next_register = self.allocate_register(node)?;
+                self.assembly.push(format!("LOAD ${} @{}", next_register, label));
+                self.push_operand(next_register);
+            },
+            Token::Identifier{ ref name, .. } => {
+                let register = self.load_variable(node, name)?;
+                self.push_operand(register);
+            },
+            Token::If{ .. } => {
+                self.emit_conditional(&[node])?;
+            },
+            Token::Elif{ .. } | Token::Else{ .. } => {
+                return Err(CodegenError::DanglingBranch{ token: node.clone() });
+            },
+            Token::Conditional{ ref branches, .. } => {
+                let branches: Vec<&Token> = branches.iter().collect();
+                self.emit_conditional(&branches)?;
+            },
+            Token::Factor{ ref value, .. } => {
+                self.visit_token(value)?;
+            },
+            Token::BinaryOperation{ ref operator, ref left, ref right, .. } => {
+                // If we are doing a variable assignment, we need to handle it a bit differently
+                if **operator == Token::Assignment {
+                    let identifier = match Compiler::assignment_target(left) {
+                        Some(identifier) => identifier,
+                        None => {
+                            return Err(CodegenError::InvalidAssignmentTarget{ token: (**left).clone() });
+                        }
+                    };
+                    if let Some(elements) = Compiler::list_literal(right) {
+                        return self.assign_list(node, &identifier, elements);
+                    }
+                    self.visit_token(right)?;
+                    self.assign_variable(node, &identifier)?;
+                } else {
+                    // Both operands are on the operand stack by the time the operator is visited
+                    self.visit_token(left)?;
+                    self.visit_token(right)?;
+                    self.visit_token(operator)?;
+                }
+            },
+            Token::UnaryOperation{ .. } if Compiler::constant(node).is_some() => {
+                // A negative number is loaded as it is. Negating 2147483648 at runtime would
+                // overflow.
+                let next_register = self.allocate_register(node)?;
+                self.emit_load(next_register, Compiler::constant(node).unwrap_or_default());
+                self.push_operand(next_register);
+            },
+            Token::UnaryOperation{ ref operator, ref operand, .. } => {
+                self.visit_token(operand)?;
+                // `-` and `+` mean something else in front of one operand than between two
+                match **operator {
+                    Token::SubtractionOperator => self.emit_negation(node)?,
+                    Token::AdditionOperator => {},
+                    _ => self.visit_token(operator)?,
+                }
+            },
+            Token::FunctionName{ ref name, .. } => {
+
+            },
+            Token::Parameters{ ref names, .. } => {
+                // Nothing can be spilled yet, since the arguments haven't been popped
+                for name in names {
+                    let next_register = match self.free_registers.pop() {
+                        Some(register) => register,
+                        None => return Err(CodegenError::RegisterExhaustion{ token: node.clone() }),
+                    };
+                    self.new_variable(name, next_register);
+                }
+            },
+            Token::FunctionBody{ ref expressions, .. } => {
+                self.visit_statements(expressions, &[])?;
+            },
+            Token::Function{ ref name, ref parameters, ref body, .. } => {
+                let function_name = name.to_string().trim().to_string();
+                // Functions are emitted where they are defined, so jump over the body
+                let end_label = self.next_label("endfunction");
+                self.emit_jump(&end_label);
+                self.emit_label(&Compiler::function_label(&function_name));
+                let parameter_count = match **parameters {
+                    Token::Parameters{ ref names, .. } => names.len(),
+                    _ => 0,
+                };
+                self.functions.push((function_name, parameter_count));
+                self.function_depth += 1;
+                // The function spills into a frame of its own, which starts above the return address
+                let outer_frame = mem::replace(&mut self.frame, Frame::new(self.scopes.len()));
+                self.new_scope();
+                self.visit_token(parameters)?;
+                // The caller pushed the arguments before `CALL` pushed the return address
+                if let Token::Parameters{ ref names, .. } = **parameters {
+                    self.emit_pop(RETURN_REGISTER);
+                    for name in names.iter().rev() {
+                        if let Some(register) = self.get_variable(name) {
+                            self.emit_pop(register);
+                        }
+                    }
+                    self.emit_push(RETURN_REGISTER);
+                }
+                self.visit_token(body)?;
+                // A function that ends without a return statement returns `None`, which is 0
+                self.emit_frame_cleanup(RETURN_REGISTER);
+                self.assembly.push(format!("LOAD ${} #0", RETURN_REGISTER));
+                self.assembly.push("RET".into());
+                self.remove_scope();
+                self.frame = outer_frame;
+                self.function_depth -= 1;
+                self.emit_label(&end_label);
+            },
+            Token::FunctionCall{ ref name, ref arguments, .. } => {
+                self.emit_call(node, name, arguments)?;
+            },
+            Token::ReturnStatement{ ref parameters, .. } => {
+                if self.function_depth == 0 {
+                    return Err(CodegenError::ReturnOutsideFunction{ token: node.clone() });
+                }
+                self.visit_token(parameters)?;
+                let register = self.pop_operand(node)?;
+                self.emit_move(register, RETURN_REGISTER);
+                // Nothing is needed after this, so the slots are popped into the same register
+                self.emit_frame_cleanup(register);
+                self.free_register(register);
+                // The return address is on top once the frame is gone
+                self.assembly.push("RET".into());
+                let after_label = self.next_label("afterreturn");
+                self.emit_label(&after_label);
+            },
+            Token::Arguments{ ref values, .. } => {
+                // `emit_call` takes the values off the operand stack
+                for value in values {
+                    self.visit_token(value)?;
+                }
+            },
+            Token::ForLoop{ ref start, ref body, .. } => {
+                if let Token::ForLoopStart{ ref variable_name, ref collection_name, .. } = **start {
+                    self.emit_for_loop(node, variable_name, collection_name, body)?;
+                }
+            },
+            Token::ForLoopStart{ .. } => {
+                // Handled by `Token::ForLoop`, which needs the body as well
+            },
+            Token::ForLoopBody{ ref expressions, .. } => {
+                self.visit_statements(expressions, &[])?;
+            },
+            Token::WhileLoop{ ref start, ref body, .. } => {
+                let header_label = self.next_label("while");
+                let end_label = self.next_label("endwhile");
+                self.declare_assigned(node)?;
+                // The condition is checked again before every iteration
+                let layout = self.layout();
+                self.emit_label(&header_label);
+                self.visit_token(start)?;
+                let condition = self.pop_operand(node)?;
+                self.emit_jump_if_false(condition, &end_label);
+                self.free_register(condition);
+                let finished = self.layout();
+                self.visit_token(body)?;
+                self.reconcile(node, &layout)?;
+                self.emit_jump(&header_label);
+                self.emit_label(&end_label);
+                self.adopt(finished);
+            },
+            Token::WhileLoopStart{ ref expression, .. } => {
+                self.visit_token(expression)?;
+            },
+            Token::WhileLoopBody{ ref expressions, .. } => {
+                self.emit_block(expressions)?;
+            },
+            Token::Eof => {
+
+            },
+            Token::ListInterior{ ref body, .. } => {
+
+            },
+            Token::List { .. } | Token::Tuple { .. } => {
+                return Err(CodegenError::UnassignedList{ token: node.clone() });
+            },
+            Token::Dictionary { ref keys, ref values, .. } => {
+
+            },
+            Token::DictionaryKeyValuePair { ref key, ref value, .. } => {
+
+            },
+            Token::Program{ ref expressions, .. } => {
+                self.write_prologue();
+                self.reserve_globals(node, expressions)?;
+                self.visit_statements(expressions, &[])?;
+                for (name, found, call) in &self.calls {
+                    match self.parameter_count(name) {
+                        None => {
+                            return Err(CodegenError::UndefinedFunction{ name: name.to_string(), token: call.clone() });
+                        },
+                        Some(expected) if expected != *found => {
+                            let (name, expected, found, token) = (name.to_string(), expected as u32, *found as u32, call.clone());
+                            return Err(CodegenError::ArityMismatch{ name, expected, found, token });
+                        },
+                        Some(_) => {},
+                    }
+                }
+                // Execution of a program starts at `main`, once the top-level code has run
+                if self.has_function("main") {
+                    let line = format!("CALL @{}", Compiler::function_label("main"));
+                    self.assembly.push(line);
+                }
+                self.assembly.push("HLT".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a value on the `Compiler`'s operand stack lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    /// The value is in this register
+    Register(u8),
+    /// The value was pushed onto the Iridium stack to free up its register
+    Spilled,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(register) => write!(f, "${}", register),
+            Operand::Spilled => write!(f, "spilled"),
+        }
+    }
+}
+
+/// Where the value of a variable is
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Location {
+    /// The variable is in this register
+    Register(u8),
+    /// The variable was spilled into the slot at this offset of its `Frame`
+    Slot(i32),
+}
+
+/// Where every variable was at some point, saved by `Compiler::layout`
+#[derive(Clone)]
+struct Layout {
+    scopes: Vec<Scope>,
+    frame: Frame,
+    free_registers: Vec<u8>,
+}
+
+/// The state `Compiler::snapshot` saves
+pub struct Snapshot {
+    free_registers: Vec<u8>,
+    used_registers: Vec<Operand>,
+    assembly_length: usize,
+    scopes: Vec<Scope>,
+    scope_pointer: usize,
+    result_register: Option<u8>,
+    functions: Vec<(String, usize)>,
+    function_depth: usize,
+    strings_length: usize,
+    calls_length: usize,
+    frame: Frame,
+    mentions: Vec<String>,
+    upcoming_length: usize,
+    entry: Option<String>,
+}
+
+mod tests {
+    use super::*;
+    use lexer::{Lexemes, Span};
+    use program_parsers::{lex_source, parse_source};
+    use conditional_parsers::elif_block;
+    use iridium::vm::VM;
+
+    fn generate_test_program(expr: &str) -> Token {
+        parse_source(expr).unwrap()
+    }
+
+    /// Compiles `expr` and runs it on a VM until it halts
+    fn run_test_program(expr: &str) -> VM {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program(expr);
+        compiler.visit_token(&test_program).unwrap();
+        let bytecode = compiler.compile().unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(bytecode);
+        vm.run();
+        vm
+    }
+
+    #[test]
+    fn test_visit_addition_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("1+2");
+        assert!(compiler.visit_token(&test_program).is_ok());
+    }
+
+    #[test]
+    fn test_visit_subtraction_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2-1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+    }
+
+    #[test]
+    fn test_visit_multiplication_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2*1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_visit_division_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2/1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_visit_greater_than_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2>1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_visit_less_than_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2<1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_visit_less_than_or_equal_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2<=1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_visit_greater_than_or_equal_token() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("2>=1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_nested_operators() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("(4*3)-1");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_variable_assignment() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("x = 4");
+        assert!(compiler.visit_token(&test_program).is_ok());
+        let bytecode = compiler.compile();
+    }
+
+    #[test]
+    fn test_function_call_with_args() {
+        let vm = run_test_program("a = 1\ndef testfunc(a):\n\t3+4\n\treturn a * 10;\ndef main():\n\treturn testfunc(a + 4) + a");
+        assert_eq!(vm.registers[31], 51);
+    }
+    #[test]
+    fn test_function_declaration() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("def testfunc():\n\t3+4\n\treturn 0;");
+        assert!(compiler.visit_token(&test_program).is_ok());
+    }
+
+    #[test]
+    fn test_function_call_assignment() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("def testfunc():\n    return 1\nx = testfunc()");
+        assert!(compiler.visit_token(&test_program).is_ok());
+    }
+
+    #[test]
+    fn test_undefined_function() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("def main():\n    return helper(1)\n");
+        match compiler.visit_token(&test_program) {
+            Err(CodegenError::UndefinedFunction{ name, token }) => {
+                assert_eq!(name, "helper");
+                let span = token.span().unwrap();
+                assert_eq!((span.line, span.column), (2, 12));
+            },
+            other => panic!("Expected an undefined function error, got {:?}", other),
+        }
+        // A function can be called in a body before it is defined
+        let vm = run_test_program("def main():\n    return helper(1)\n\ndef helper(x):\n    return x + 1\n");
+        assert_eq!(vm.registers[31], 2);
+    }
+
+    #[test]
+    fn test_arity_mismatch() {
+        let cases = vec![
+            ("def f(a):\n    return a\n\ndef main():\n    return f(1, 2)\n", 1, 2, (5, 12)),
+            ("def main():\n    return f(1)\n\ndef f(a, b):\n    return a + b\n", 2, 1, (2, 12)),
+            ("def f():\n    return 1\n\nx = f(3)\n", 0, 1, (4, 5)),
+        ];
+        for (source, expected_count, found_count, position) in cases {
+            let mut compiler = Compiler::new();
+            let test_program = generate_test_program(source);
+            match compiler.visit_token(&test_program) {
+                Err(CodegenError::ArityMismatch{ name, expected, found, token }) => {
+                    assert_eq!((name.as_str(), expected, found), ("f", expected_count, found_count), "{}", source);
+                    let span = token.span().unwrap();
+                    assert_eq!((span.line, span.column), position, "{}", source);
+                },
+                other => panic!("Expected an arity mismatch for {:?}, got {:?}", source, other),
+            }
+        }
+        let error = CodegenError::ArityMismatch{ name: "f".into(), expected: 1, found: 2, token: Token::Eof };
+        assert_eq!(error.to_string(), "Function f takes 1 argument but is called with 2");
+    }
+
+    #[test]
+    fn test_function_return_values() {
+        let vm = run_test_program(
+r#"
+def test():
+    return 1;
+
+y = test()
+
+def main():
+    return y + test()
+"#
+        );
+        assert_eq!(vm.registers[31], 2);
+    }
+
+    #[test]
+    fn test_main_is_called() {
+        let vm = run_test_program("x = 40\ndef main():\n    y = x + 2\n    return y;\n");
+        assert_eq!(vm.registers[31], 42);
+    }
+
+    #[test]
+    fn test_functions_read_globals_assigned_later() {
+        let vm = run_test_program("def main():\n    a = 7\n    return f()\nx = 3\ndef f():\n    return x\n");
+        assert_eq!(vm.registers[31], 3);
+        let vm = run_test_program("def h():\n    a = 7\n    return f() + a\nx = 3\ndef f():\n    return x\ndef main():\n    return h()\n");
+        assert_eq!(vm.registers[31], 10);
+        let vm = run_test_program("def main():\n    return f()\ndef f():\n    return x * 2\nx = 21\n");
+        assert_eq!(vm.registers[31], 42);
+    }
+
+    #[test]
+    fn test_compile_reports_undefined_label() {
+        let mut compiler = Compiler::new();
+        compiler.write_prologue();
+        compiler.assembly.push("CALL @fntestfunc".into());
+        match compiler.compile() {
+            Err(CompileError::UndefinedLabel{ label, line }) => {
+                assert_eq!(label, "fntestfunc");
+                assert_eq!(line.text, "CALL @fntestfunc");
+            },
+            other => panic!("Expected an undefined label error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compile_reports_invalid_instruction() {
+        let mut compiler = Compiler::new();
+        compiler.write_prologue();
+        compiler.assembly.push("LOAD $0 #1".into());
+        compiler.assembly.push("FOO $0".into());
+        match compiler.compile() {
+            Err(CompileError::InvalidInstruction{ line, .. }) => {
+                assert_eq!(line, AssemblyLine{ number: 4, text: "FOO $0".into() });
+            },
+            other => panic!("Expected an invalid instruction error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compile_reports_assembler_errors() {
+        let mut compiler = Compiler::new();
+        compiler.write_prologue();
+        compiler.emit_label("test");
+        compiler.emit_label("test");
+        match compiler.compile() {
+            Err(CompileError::Assembler{ errors, line }) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(line.unwrap().number, 4);
+            },
+            other => panic!("Expected an assembler error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("x + 1");
+        match compiler.visit_token(&test_program) {
+            Err(CodegenError::UndefinedVariable{ name, .. }) => assert_eq!(name, "x"),
+            other => panic!("Expected an undefined variable error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_operand_stack_underflow() {
+        let mut compiler = Compiler::new();
+        let result = compiler.visit_token(&Token::AdditionOperator);
+        assert_eq!(result, Err(CodegenError::OperandStackUnderflow{ token: Token::AdditionOperator }));
+    }
+
+    #[test]
+    fn test_list_too_long() {
+        let elements: Vec<String> = (0..40).map(|i| i.to_string()).collect();
+        let source = format!("xs = [{}]\n", elements.join(", "));
+        let mut compiler = Compiler::new();
+        match compiler.visit_token(&generate_test_program(&source)) {
+            Err(CodegenError::ListTooLong{ name, length, limit, token }) => {
+                assert_eq!((name.as_str(), length), ("xs", 40));
+                assert_eq!(limit, 30);
+                assert_eq!(token.span().unwrap().line, 1);
+            },
+            other => panic!("Expected the list to be too long, got {:?}", other),
+        }
+        let elements: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        let list = format!("xs = [{}]", elements.join(", "));
+        assert_eq!(run_statements(&[&list, "total = 0", "for e in xs:\n    total = total + e", "total"]), 190);
+    }
+
+    #[test]
+    fn test_register_exhaustion() {
+        let mut compiler = Compiler::new();
+        compiler.free_registers.clear();
+        let token = Token::Integer{ value: 1, span: Span::default() };
+        let result = compiler.visit_token(&token);
+        assert_eq!(result, Err(CodegenError::RegisterExhaustion{ token }));
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("3 = 4");
+        match compiler.visit_token(&test_program) {
+            Err(CodegenError::InvalidAssignmentTarget{ .. }) => {},
+            other => panic!("Expected an invalid assignment target error, got {:?}", other),
+        }
+    }
+
+    /// A `main` that keeps 25 variables live while it computes a nested expression, so some
+    /// of the operands of that expression have to be spilled
+    fn spilling_program() -> String {
+        let mut source = String::from("def main():\n");
+        for i in 1..26 {
+            source.push_str(&format!("    a{} = {}\n", i, i));
+        }
+        source.push_str("    b = 1+(2+(3+(4+(5+(6+(7+(8+0)))))))\n    total = b");
+        for i in 1..26 {
+            source.push_str(&format!(" + a{}", i));
+        }
+        source.push_str("\n    return total;\n");
+        source
+    }
+
+    #[test]
+    fn test_spill_nested_expression() {
+        let vm = run_test_program(&spilling_program());
+        assert_eq!(vm.registers[31], 361);
+    }
+
+    #[test]
+    fn test_spilled_operands_are_popped() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program(&spilling_program());
+        compiler.visit_token(&test_program).unwrap();
+        // `main` also pops and pushes its return address once
+        let pushes = compiler.assembly.iter().filter(|line| line.starts_with("PUSH")).count();
+        let pops = compiler.assembly.iter().filter(|line| line.starts_with("POP")).count();
+        assert!(pushes > 1);
+        assert_eq!(pushes, pops);
+        assert_eq!(compiler.frame.bp_offset(), 0);
+    }
+
+    /// A `main` that assigns `count` variables, which all stay live until `body` uses them
+    fn live_variables_program(count: usize, body: &str) -> String {
+        let mut source = String::from("def main():\n");
+        for i in 0..count {
+            source.push_str(&format!("    v{} = {}\n", i, i));
+        }
+        source.push_str(body);
+        source
+    }
+
+    #[test]
+    fn test_spill_variables() {
+        let mut body = String::from("    total = 0\n");
+        for i in 0..35 {
+            body.push_str(&format!("    total = total + v{}\n", i));
+        }
+        body.push_str("    return total\n");
+        let source = live_variables_program(35, &body);
+        let vm = run_test_program(&source);
+        assert_eq!(vm.registers[31], (0..35).sum::<i32>());
+
+        let mut compiler = Compiler::new();
+        compiler.visit_token(&generate_test_program(&source)).unwrap();
+        let pushes = compiler.assembly.iter().filter(|line| line.starts_with("PUSH")).count();
+        let pops = compiler.assembly.iter().filter(|line| line.starts_with("POP")).count();
+        assert!(pushes > 1);
+        assert_eq!(pushes, pops);
+    }
+
+    #[test]
+    fn test_spilled_variables_in_loops_and_branches() {
+        // Every variable is used again at the end, so they all stay live through the loops
+        let mut sum = String::new();
+        for i in 0..36 {
+            sum.push_str(&format!("    total = total + v{}\n", i));
+        }
+        let body = format!("    total = 0\n    i = 0\n    while i < 3:\n        total = total + v34\n\
+                            \x20       if i == 1:\n            w = v31 + v2\n            total = total + w\n\
+                            \x20       else:\n            total = total - v1\n        i = i + 1\n{}    return total\n", sum);
+        let vm = run_test_program(&live_variables_program(36, &body));
+        assert_eq!(vm.registers[31], 3 * 34 + 33 - 2 + (0..36).sum::<i32>());
+
+        let body = format!("    total = 0\n    xs = [1, 2, 3]\n    for x in xs:\n        y = x * v30\n        total = total + y + v34\n{}\
+                            \x20   return total\n", sum);
+        let vm = run_test_program(&live_variables_program(36, &body));
+        assert_eq!(vm.registers[31], 6 * 30 + 3 * 34 + (0..36).sum::<i32>());
+    }
+
+    #[test]
+    fn test_dead_variables_free_their_registers() {
+        // Far more variables than registers, but only a few are live at once
+        let mut source = String::from("def main():\n    a0 = 1\n");
+        for i in 1..50 {
+            source.push_str(&format!("    a{} = a{} + 1\n", i, i - 1));
+        }
+        source.push_str("    return a49;\n");
+        let vm = run_test_program(&source);
+        assert_eq!(vm.registers[31], 50);
+    }
+
+    #[test]
+    fn test_function_arguments_are_popped() {
+        let vm = run_test_program("def double(n):\n    y = n * 2\n    return y;\n\ndef main():\n    x = 21\n    y = double(x)\n    return y;\n");
+        assert_eq!(vm.registers[31], 42);
+    }
+
+    #[test]
+    fn test_arguments_are_expressions() {
+        let functions = "def sub(a, b):\n    return a - b\n\ndef double(n):\n    return n * 2\n\n";
+        for &(call, expected) in &[("sub(10, 3)", 7), ("double(1)", 2), ("double(x + 1)", 8), ("sub(double(x), -x)", 9), ("double(double(double(x)))", 24), ("sub(x, 1,)", 2)] {
+            let source = format!("{}def main():\n    x = 3\n    return {}\n", functions, call);
+            let vm = run_test_program(&source);
+            assert_eq!(vm.registers[31], expected, "{}", call);
+        }
+    }
+
+    #[test]
+    fn test_calls_keep_registers_in_use() {
+        let functions = "def double(n):\n    y = n * 2\n    return y\n\n";
+        let vm = run_test_program(&format!("{}def main():\n    x = 2\n    y = double(x)\n    return x + y\n", functions));
+        assert_eq!(vm.registers[31], 6);
+        let vm = run_test_program(&format!("{}def main():\n    return 1 + double(3) * double(4)\n", functions));
+        assert_eq!(vm.registers[31], 49);
+        let vm = run_test_program(&format!("{}def main():\n    xs = [1, 2, 3]\n    total = 0\n    for x in xs:\n        total = total + double(x)\n    return total\n", functions));
+        assert_eq!(vm.registers[31], 12);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let vm = run_test_program("def factorial(n):\n    if n < 2:\n        return 1\n    return n * factorial(n - 1)\n\ndef main():\n    return factorial(5)\n");
+        assert_eq!(vm.registers[31], 120);
+        let vm = run_test_program("def fib(n):\n    if n < 2:\n        return n\n    return fib(n - 1) + fib(n - 2)\n\ndef main():\n    return fib(10)\n");
+        assert_eq!(vm.registers[31], 55);
+    }
+
+    #[test]
+    fn test_docstrings_are_not_compiled() {
+        let source = "def double(n):\n    \"\"\"Returns twice n\"\"\"\n    return n * 2\n\n\
+                      def main():\n    '''The entry point'''\n    x = 21  # half the answer\n    return double(x)\n";
+        let mut compiler = Compiler::new();
+        compiler.visit_token(&generate_test_program(source)).unwrap();
+        assert!(compiler.listing().iter().all(|line| !line.contains(".integer")));
+        let vm = run_test_program(source);
+        assert_eq!(vm.registers[31], 42);
+    }
+
+    /// Builds a program out of statements that are parsed one at a time
+    fn generate_statements(sources: &[&str]) -> Token {
+        let mut expressions = vec![];
+        for source in sources {
+            match parse_source(source) {
+                Ok(Token::Program{ expressions: parsed, .. }) => expressions.extend(parsed),
+                other => panic!("Unable to parse {}: {:?}", source, other),
+            }
+        }
+        Token::Program{ expressions, span: Span::default() }
+    }
+
+    /// Compiles and runs `statements`, and returns the value of the last one
+    fn run_statements(statements: &[&str]) -> i32 {
+        let mut compiler = Compiler::new();
+        compiler.visit_token(&generate_statements(statements)).unwrap();
+        let bytecode = compiler.compile().unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(bytecode);
+        vm.run();
+        vm.registers[compiler.result_register().unwrap() as usize]
+    }
+
+    #[test]
+    fn test_comparison_values() {
+        assert_eq!(run_statements(&["3 > 2"]), 1);
+        assert_eq!(run_statements(&["2 > 3"]), 0);
+        assert_eq!(run_statements(&["2 <= 2"]), 1);
+        assert_eq!(run_statements(&["2 == 3"]), 0);
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        assert_eq!(run_statements(&["1 + 2 * 3"]), 7);
+        assert_eq!(run_statements(&["2 * 3 + 1"]), 7);
+        assert_eq!(run_statements(&["(1 + 2) * 3"]), 9);
+        assert_eq!(run_statements(&["10 - 4 - 3"]), 3);
+        assert_eq!(run_statements(&["12 / 3 * 2"]), 8);
+        assert_eq!(run_statements(&["1 + 1 == 2"]), 1);
+        assert_eq!(run_statements(&["x = 1 + 2 * 3", "x"]), 7);
+    }
+
+    #[test]
+    fn test_negation() {
+        assert_eq!(run_statements(&["-(-3)"]), 3);
+        assert_eq!(run_statements(&["x = 5", "-x"]), -5);
+        assert_eq!(run_statements(&["x = 5", "-x * 2"]), -10);
+        assert_eq!(run_statements(&["x = 5", "-(x + 1)"]), -6);
+        assert_eq!(run_statements(&["x = 5", "3 - -x"]), 8);
+        assert_eq!(run_statements(&["x = 5", "+x"]), 5);
+    }
+
+    #[test]
+    fn test_logical_not() {
+        assert_eq!(run_statements(&["not 0"]), 1);
+        assert_eq!(run_statements(&["not 7"]), 0);
+        assert_eq!(run_statements(&["x = 3", "not not x"]), 1);
+        assert_eq!(run_statements(&["x = 3", "not x == 4"]), 1);
+        assert_eq!(run_statements(&["x = 3", "not x == 3 or x > 2"]), 1);
+    }
+
+    #[test]
+    fn test_logical_and_or() {
+        assert_eq!(run_statements(&["2 and 1"]), 1);
+        assert_eq!(run_statements(&["2 and 0"]), 0);
+        assert_eq!(run_statements(&["0 and 3"]), 0);
+        assert_eq!(run_statements(&["0 or 3"]), 1);
+        assert_eq!(run_statements(&["0 or 0"]), 0);
+        assert_eq!(run_statements(&["x = 4", "y = 8", "x and y"]), 1);
+        assert_eq!(run_statements(&["x = 4", "y = x or 0", "x + y"]), 5);
+    }
+
+    #[test]
+    fn test_variables_assigned_in_blocks_outlive_them() {
+        let branches = "if x > 5:\n    y = 1\nelse:\n    y = 2";
+        assert_eq!(run_statements(&["x = 7", branches, "z = y + 1", "z"]), 2);
+        assert_eq!(run_statements(&["x = 3", branches, "z = y + 1", "z"]), 3);
+        assert_eq!(run_statements(&["x = 3", "if x > 5:\n    y = 1", "y"]), 0);
+        assert_eq!(run_statements(&["i = 0", "while i < 3:\n    if i > 0:\n        last = i\n    i = i + 1", "last"]), 2);
+        assert_eq!(run_statements(&["xs = [4, 5]", "for x in xs:\n    total = x", "total"]), 5);
+    }
+
+    #[test]
+    fn test_lists_assigned_in_blocks_outlive_them() {
+        let branches = "if x > 1:\n    xs = [1, 2]\nelse:\n    xs = [5, 6]";
+        let sum = "for e in xs:\n    total = total + e";
+        assert_eq!(run_statements(&["x = 2", "total = 0", branches, sum, "total"]), 3);
+        assert_eq!(run_statements(&["x = 0", "total = 0", branches, sum, "total"]), 11);
+        assert_eq!(run_statements(&["i = 0", "while i < 3:\n    ys = (i, i * 10)\n    i = i + 1", "total = 0", "for e in ys:\n    total = total + e", "total"]), 22);
+        let cases = [
+            "x = 2\nif x > 1:\n    xs = [1]\nelse:\n    xs = [5, 6]\nfor e in xs:\n    y = e\n",
+            "xs = [1, 2]\nwhile True:\n    xs = [1, 2, 3]\n",
+        ];
+        for source in &cases {
+            let mut compiler = Compiler::new();
+            match compiler.visit_token(&generate_test_program(source)) {
+                Err(CodegenError::ListLengthMismatch{ name, .. }) => assert_eq!(name, "xs"),
+                other => panic!("Expected a list length mismatch for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_negated_variable_keeps_its_value() {
+        assert_eq!(run_statements(&["x = 5", "y = -x", "x"]), 5);
+        assert_eq!(run_statements(&["x = 5", "y = not x", "x"]), 5);
+    }
+
+    #[test]
+    fn test_not_equal() {
+        assert_eq!(run_statements(&["3 != 2"]), 1);
+        assert_eq!(run_statements(&["3 != 3"]), 0);
+        assert_eq!(run_statements(&["x = 2", "x + 1 != 3"]), 0);
+    }
+
+    #[test]
+    fn test_floor_division_and_modulo() {
+        for &(left, right) in &[(7, 2), (-7, 2), (7, -2), (-7, -2), (6, 3), (-6, 3), (0, 5)] {
+            let setup = format!("a = {}", left);
+            let divisor = format!("b = {}", right);
+            let quotient = (f64::from(left) / f64::from(right)).floor() as i32;
+            assert_eq!(run_statements(&[&setup, &divisor, "a // b"]), quotient, "{} // {}", left, right);
+            assert_eq!(run_statements(&[&setup, &divisor, "a % b"]), left - quotient * right, "{} % {}", left, right);
+        }
+        assert_eq!(run_statements(&["1 + 7 // 2 * 2"]), 7);
+        assert_eq!(run_statements(&["a = 17", "a % 5", "a"]), 17);
+    }
+
+    #[test]
+    fn test_exponent() {
+        assert_eq!(run_statements(&["2 ** 10"]), 1024);
+        assert_eq!(run_statements(&["5 ** 0"]), 1);
+        assert_eq!(run_statements(&["2 ** 3 ** 2"]), 512);
+        assert_eq!(run_statements(&["-2 ** 2"]), -4);
+        assert_eq!(run_statements(&["(-2) ** 3"]), -8);
+        assert_eq!(run_statements(&["n = 3", "x = n ** n", "n + x"]), 30);
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(run_statements(&["12 & 10"]), 8);
+        assert_eq!(run_statements(&["12 | 3"]), 15);
+        assert_eq!(run_statements(&["12 ^ 10"]), 6);
+        assert_eq!(run_statements(&["~5"]), -6);
+        assert_eq!(run_statements(&["x = 0", "~x"]), -1);
+    }
+
+    #[test]
+    fn test_shifts() {
+        assert_eq!(run_statements(&["1 << 4"]), 16);
+        assert_eq!(run_statements(&["256 >> 4"]), 16);
+        assert_eq!(run_statements(&["-16 >> 2"]), -4);
+        assert_eq!(run_statements(&["5 << 0"]), 5);
+        assert_eq!(run_statements(&["n = 3", "x = 1 << n", "x + n"]), 11);
+    }
+
+    #[test]
+    fn test_bit_flags() {
+        assert_eq!(run_statements(&["flags = 5", "flags = flags | 1 << 3", "flags"]), 13);
+        assert_eq!(run_statements(&["flags = 13", "flags & 1 << 2 != 0"]), 1);
+        assert_eq!(run_statements(&["flags = 13", "flags & ~4"]), 9);
+        assert_eq!(run_statements(&["header = 0xABCD", "header >> 8 & 0xFF"]), 0xAB);
+    }
+
+    #[test]
+    fn test_names_with_underscores() {
+        assert_eq!(run_statements(&["tile_count = 3", "_private = tile_count * 2", "maxHP2 = _private + 1", "maxHP2"]), 7);
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        assert_eq!(run_statements(&["0xFF"]), 255);
+        assert_eq!(run_statements(&["0b1010 + 0o17"]), 25);
+        assert_eq!(run_statements(&["x = 1_000_000", "x + 1"]), 1_000_001);
+        assert_eq!(run_statements(&["100000"]), 100_000);
+        assert_eq!(run_statements(&["32768"]), 32768);
+        assert_eq!(run_statements(&["-70000"]), -70000);
+        assert_eq!(run_statements(&["0xFFFFFFFF"]), -1);
+        assert_eq!(run_statements(&["2147483647"]), i32::MAX);
+        assert_eq!(run_statements(&["-2147483648"]), i32::MIN);
+        assert_eq!(run_statements(&["-2147483648.0"]), i32::MIN);
+        assert_eq!(run_statements(&["x = -2147483648", "x + 1"]), i32::MIN + 1);
+        assert_eq!(run_statements(&["- -7"]), 7);
+        let mut compiler = Compiler::new();
+        compiler.visit_token(&generate_test_program("x = -2147483648\n")).unwrap();
+        assert!(compiler.assembly.iter().all(|line| !line.starts_with("SUB")));
+    }
+
+    #[test]
+    fn test_float_literals() {
+        assert_eq!(run_statements(&["x = 1e9", "x"]), 1_000_000_000);
+        assert_eq!(run_statements(&["2.0 * 3"]), 6);
+        assert_eq!(run_statements(&["1_000_000.0"]), 1_000_000);
+        assert_eq!(run_statements(&["-5e4"]), -50000);
+        for source in &["x = .5\n", "x = 1.5\n", "x = 2.5e-1\n"] {
+            let mut compiler = Compiler::new();
+            let test_program = generate_test_program(source);
+            match compiler.visit_token(&test_program) {
+                Err(CodegenError::UnsupportedFloat{ token, .. }) => {
+                    let span = token.span().unwrap();
+                    assert_eq!((span.line, span.column), (1, 5), "{}", source);
+                },
+                other => panic!("Expected an unsupported float for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_strings_are_interned() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("a = \"hi\"\nb = 'hi'\nc = \"yo!!\"\n");
+        compiler.visit_token(&test_program).unwrap();
+        let data: Vec<String> = compiler.listing().into_iter().filter(|line| line.contains(".integer")).collect();
+        assert_eq!(data, vec![
+            "string0: .integer #26984",
+            "string1: .integer #555839353",
+            "string1word1: .integer #0",
+        ]);
+        assert_eq!(compiler.listing()[0], ".data");
+        assert_eq!(compiler.listing()[4], ".code");
+    }
+
+    #[test]
+    fn test_strings_are_in_bytecode() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("a = \"\\u{e9}\\n\"\nb = \"it's\"\n");
+        compiler.visit_token(&test_program).unwrap();
+        let bytecode = compiler.compile().unwrap();
+        let code_start = PIE_HEADER_LENGTH + 4;
+        assert_eq!(&bytecode[PIE_HEADER_LENGTH..code_start], &[12, 0, 0, 0]);
+        assert_eq!(&bytecode[code_start..code_start + 12], "\u{e9}\n\0it's\0\0\0\0".as_bytes());
+    }
+
+    #[test]
+    fn test_string_values_are_offsets() {
+        assert_eq!(run_statements(&["a = \"hi\"", "a"]), 0);
+        assert_eq!(run_statements(&["a = \"hi\"", "b = \"there\"", "b"]), 4);
+        assert_eq!(run_statements(&["a = \"hi\"", "b = \"there\"", "c = \"hi\"", "c"]), 0);
+    }
+
+    #[test]
+    fn test_code_after_strings_runs() {
+        let vm = run_test_program(
+            "greeting = \"Hello, world! This is long enough to take several words.\"\n\
+             def main():\n    x = 0\n    while x < 42:\n        x = x + 1\n    return x\n"
+        );
+        assert_eq!(vm.registers[31], 42);
+    }
+
+    #[test]
+    fn test_boolean_and_none_values() {
+        assert_eq!(run_statements(&["True"]), 1);
+        assert_eq!(run_statements(&["False"]), 0);
+        assert_eq!(run_statements(&["None"]), 0);
+        assert_eq!(run_statements(&["True and False"]), 0);
+        assert_eq!(run_statements(&["not None"]), 1);
+        assert_eq!(run_statements(&["(3 > 2) == True"]), 1);
+        assert_eq!(run_statements(&["None == False"]), 1);
+    }
+
+    #[test]
+    fn test_constants_in_conditions_returns_and_lists() {
+        let vm = run_test_program(
+            "def check(x):\n    if x:\n        return True\n    return None\n\n\
+             def main():\n    flags = [True, False, True, None]\n    total = 0\n    for flag in flags:\n        total = total + flag\n    \
+             done = False\n    while not done:\n        done = True\n        total = total * 10\n    \
+             if check(total) == True:\n        return total\n    return 0\n"
+        );
+        assert_eq!(vm.registers[31], 20);
+    }
+
+    #[test]
+    fn test_logical_precedence() {
+        assert_eq!(run_statements(&["1 == 1 or 1 == 2 and 1 == 2"]), 1);
+        assert_eq!(run_statements(&["1 == 2 and 1 == 2 or 1 == 1"]), 1);
+        assert_eq!(run_statements(&["1 == 2 or 1 == 1 and 1 == 2"]), 0);
+    }
+
+    #[test]
+    fn test_if_elif_else() {
+        for &(x, expected) in &[("x = 7", 1), ("x = 4", 2), ("x = 1", 3)] {
+            let result = run_statements(&[
+                x,
+                "y = 0",
+                "if x > 5:\n    y = 1\nelif x > 3:\n    y = 2\nelse:\n    y = 3",
+                "y",
+            ]);
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn test_only_first_matching_branch_runs() {
+        let result = run_statements(&[
+            "y = 0",
+            "if 1 < 2:\n    y = y + 1\nelif 2 < 3:\n    y = y + 10",
+            "y",
+        ]);
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        let result = run_statements(&["y = 5", "if y < 3:\n    y = 1", "y"]);
+        assert_eq!(result, 5);
+        let result = run_statements(&["y = 5", "if y > 3:\n    z = y * 2\n    y = z", "y"]);
+        assert_eq!(result, 10);
+    }
+
+    #[test]
+    fn test_dangling_elif() {
+        let mut compiler = Compiler::new();
+        let mut test_program = generate_statements(&["y = 5"]);
+        if let Token::Program{ ref mut expressions, .. } = test_program {
+            let lexemes = lex_source("elif y > 3:\n    y = 1").unwrap();
+            expressions.push(elif_block(Lexemes::new(&lexemes)).unwrap().1);
+        }
+        match compiler.visit_token(&test_program) {
+            Err(CodegenError::DanglingBranch{ .. }) => {},
+            other => panic!("Expected a dangling branch error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let result = run_statements(&[
+            "i = 0",
+            "total = 0",
+            "while i < 5:\n    total = total + i * 1\n    i = i + 1",
+            "total",
+        ]);
+        assert_eq!(result, 10);
+    }
+
+    #[test]
+    fn test_while_loop_that_never_runs() {
+        let result = run_statements(&["x = 3", "while x > 5:\n    x = x + 1", "x"]);
+        assert_eq!(result, 3);
+    }
+
+    #[test]
+    fn test_while_loop_variables_are_local() {
+        let mut compiler = Compiler::new();
+        let free_registers = compiler.free_registers.len();
+        let test_program = generate_statements(&["i = 0", "while i < 3:\n    doubled = i * 2\n    i = i + 1"]);
+        compiler.visit_token(&test_program).unwrap();
+        assert_eq!(compiler.get_variable("doubled"), None);
+        assert_eq!(compiler.free_registers.len(), free_registers);
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let result = run_statements(&[
+            "xs = [1, 2, 3]",
+            "total = 0",
+            "for x in xs:\n    total = total + x",
+            "total",
+        ]);
+        assert_eq!(result, 6);
+    }
+
+    #[test]
+    fn test_for_loop_over_computed_elements() {
+        let result = run_statements(&[
+            "a = 4",
+            "xs = [a, a * 2, 10]",
+            "a = 0",
+            "total = 1",
+            "for x in xs:\n    doubled = x * 2\n    total = total + doubled",
+            "total",
+        ]);
+        assert_eq!(result, 45);
+    }
+
+    #[test]
+    fn test_for_loop_over_empty_list() {
+        let result = run_statements(&["xs = []", "total = 7", "for x in xs:\n    total = 0", "total"]);
+        assert_eq!(result, 7);
+    }
+
+    #[test]
+    fn test_for_loop_variable_is_local() {
+        let mut compiler = Compiler::new();
+        let free_registers = compiler.free_registers.len();
+        let test_program = generate_statements(&["xs = [1, 2]", "for x in xs:\n    y = x + 1"]);
+        compiler.visit_token(&test_program).unwrap();
+        assert_eq!(compiler.get_variable("x"), None);
+        assert_eq!(compiler.free_registers.len(), free_registers);
+    }
+
+    #[test]
+    fn test_tuples() {
+        let result = run_statements(&["pair = (20, 1 + 1,)", "total = (1)", "for x in pair:\n    total = total * x", "total"]);
+        assert_eq!(result, 40);
+        let mut compiler = Compiler::new();
+        match compiler.visit_token(&generate_test_program("x = 1\n(x, 2)\n")) {
+            Err(CodegenError::UnassignedList{ .. }) => {},
+            other => panic!("Expected an unassigned tuple error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_list_used_as_value() {
+        let cases = [
+            generate_statements(&["xs = [1, 2]", "ys = xs"]),
+            generate_statements(&["xs = [1, 2]", "xs + 1"]),
+            generate_test_program("def f(ys):\n    return 1\n\ndef main():\n    xs = [1, 2]\n    return f(xs)\n"),
+            generate_test_program("def main():\n    xs = (1, 2)\n    return xs\n"),
+        ];
+        for test_program in &cases {
+            let mut compiler = Compiler::new();
+            match compiler.visit_token(test_program) {
+                Err(CodegenError::ListUsedAsValue{ name, .. }) => assert_eq!(name, "xs"),
+                other => panic!("Expected a list used as a value error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_loop_over_non_list() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_statements(&["xs = 1", "for x in xs:\n    y = x + 1"]);
+        match compiler.visit_token(&test_program) {
+            Err(CodegenError::NotAList{ name, .. }) => assert_eq!(name, "xs"),
+            other => panic!("Expected a not a list error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_loops_and_conditionals_in_functions() {
+        let vm = run_test_program(
+r#"
+def main():
+    i = 0
+    odd = 0
+    while i < 7:
+        i = i + 1
+        if i / 2 * 2 == i:
+            odd = odd
+        else:
+            odd = odd + 1
+    return odd;
+"#
+        );
+        assert_eq!(vm.registers[31], 4);
+    }
+
+    #[test]
+    fn test_early_return() {
+        let vm = run_test_program("def main():\n    x = 3\n    if x > 2:\n        return x * 2\n    return 0\n");
+        assert_eq!(vm.registers[31], 6);
+    }
+
+    #[test]
+    fn test_function_without_return() {
+        let vm = run_test_program("def main():\n    x = 3\n");
+        assert_eq!(vm.registers[31], 0);
+    }
+
+    #[test]
+    fn test_statements_after_function() {
+        let vm = run_test_program("def double(x):\n    return x * 2\n\ndef main():\n    y = 4\n    return double(y)\n");
+        assert_eq!(vm.registers[31], 8);
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        let mut compiler = Compiler::new();
+        let test_program = generate_test_program("x = 1\nreturn x\n");
+        match compiler.visit_token(&test_program) {
+            Err(CodegenError::ReturnOutsideFunction{ .. }) => {},
+            other => panic!("Expected a return outside function error, got {:?}", other),
+        }
     }
 }